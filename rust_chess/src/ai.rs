@@ -5,16 +5,18 @@ use rand::prelude::*;
 use rayon::prelude::*;
 
 pub trait Ai {
-    fn play(&mut self, board: &GameEngine) -> Vec<Move>;
+    // The move to play, `None` when there is none
+    fn play(&mut self, board: &GameEngine) -> Option<Move>;
 
     fn eval_position(&self, board: &ChessBoard) -> f64;
 
     fn new(machine_player: Color) -> Self
     where Self: Sized;
 
-    fn set_depht(&mut self, depht: usize) {
-        todo!()
-    }
+    // Ais that do not search ignore the depht
+    fn set_depht(&mut self, depht: usize) {}
+
+    fn name(&self) -> String;
 }
 
 
@@ -32,36 +34,18 @@ impl Ai for DummyRandomIA {
         }    
     }
 
-    fn play(&mut self, engine: &GameEngine) -> Vec<Move> {
-        let mut chosen_moves = vec!();
-        let moves = engine.gen_all_moves();
-        if moves.len() == 0  {
-            return vec!()
-        };
-        let random_selection: f64 = self.rng.gen();
-        let random_selection = (random_selection * moves.len() as f64) as usize;
-        let move_tmp = moves[random_selection].clone();
-        let is_promotable = move_tmp.is_promotable(&engine.board);
-        let move_tmp_to = move_tmp.to().unwrap();
-        chosen_moves.push(move_tmp);
-        // If the move leads to a promotion, we need to return two moves
-        if  is_promotable {
-            let promotions = [
-                Move::Promote(move_tmp_to, CanPromoteTo::Rook),
-                Move::Promote(move_tmp_to, CanPromoteTo::Bishop),
-                Move::Promote(move_tmp_to, CanPromoteTo::Knight),
-                Move::Promote(move_tmp_to, CanPromoteTo::Queen)
-            ];
-            let random_selection: f64 = self.rng.gen();
-            let random_selection = (random_selection * promotions.len() as f64) as usize;
-            chosen_moves.push(promotions[random_selection].clone());
-        }
-        chosen_moves
+    fn play(&mut self, engine: &GameEngine) -> Option<Move> {
+        // Each promotion piece is a move of its own, so they are picked randomly as well
+        engine.gen_all_moves().choose(&mut self.rng).cloned()
     }
 
     fn eval_position(&self, board: &ChessBoard) -> f64 {
         todo!()
     }
+
+    fn name(&self) -> String {
+        "DummyRandomIA".into()
+    }
 }
 
 
@@ -86,15 +70,21 @@ impl Ai for BestPlayDephtOneAi {
         }    
     }
 
-    fn play(&mut self, engine: &GameEngine) -> Vec<Move> {
-        let mut chosen_moves = vec!();
+    fn play(&mut self, engine: &GameEngine) -> Option<Move> {
         let moves = engine.gen_all_moves();
+        let mult = match self.machine_player {
+            Color::Black => -1.0,
+            Color::White => 1.0
+        };
         let scores: Vec<f64> = moves.par_iter().map(|m| {
             let mut tmp_engine = engine.clone();
-            let can_promote = tmp_engine.play_once(m.clone());
-            // we will se later for the promotion
-            let position_evaluation = self.eval_position(&tmp_engine.board);
-            position_evaluation
+            match tmp_engine.play_bypass(m.clone()) {
+                Ok(_) => match variant_score(&tmp_engine) {
+                    Some(score) => mult * score,
+                    None => tmp_engine.variant.evaluation(&tmp_engine.board, self.eval_position(&tmp_engine.board))
+                },
+                Err(_) => -f64::INFINITY
+            }
         })
         .collect();
         let best_move = scores
@@ -108,8 +98,7 @@ impl Ai for BestPlayDephtOneAi {
                     (i_acc, v_acc)
                 }
             });
-        chosen_moves.push(moves[best_move.0].clone());
-        chosen_moves
+        moves.get(best_move.0).cloned()
     }
 
     fn eval_position(&self, board: &ChessBoard) -> f64 {
@@ -128,10 +117,34 @@ impl Ai for BestPlayDephtOneAi {
         }
         eval
     }
+
+    fn name(&self) -> String {
+        "BestPlayDephtOneAi".into()
+    }
 }
 
 
 
+// Promotions then captures are searched first, they are the moves most likely to cut the other branches
+fn order_moves(moves: &mut [Move]) {
+    moves.sort_by_key(|m| match m {
+        Move::Promote(_, _, CanPromoteTo::Queen) => 0,
+        Move::Promote(_, _, _) => 1,
+        Move::Take(_, _) | Move::EnPassant(_, _) => 2,
+        _ => 3
+    });
+}
+
+// The score of a game the variant ends on the board, from white's side
+fn variant_score(engine: &GameEngine) -> Option<f64> {
+    let outcome = engine.variant.board_outcome(&engine.board)?;
+    match outcome.winner() {
+        Some(Color::White) => Some(f64::INFINITY),
+        Some(Color::Black) => Some(-f64::INFINITY),
+        None => Some(0.0)
+    }
+}
+
 fn init_node_eval(is_max: bool) -> f64 {
     if is_max {
         return -f64::INFINITY;
@@ -149,7 +162,6 @@ pub struct MiniMaxAi {
 }
 
 impl MiniMaxAi {
-    // Handling promotion will suck
     fn mini_max_iterative_deepening(
         &self,
         engine: &mut GameEngine,
@@ -186,11 +198,19 @@ impl MiniMaxAi {
         is_max: bool,
         mut alpha: f64,
         mut beta: f64,
-        transposition_table: &mut HashMap<String, (usize, f64)>,
+        transposition_table: &mut HashMap<u64, (usize, f64)>,
         called: &mut i64
     ) -> f64 {
         *called += 1;
-        let curr_board_key = engine.get_board_as_key();
+        // Draws by rule end the branch, whatever the material balance
+        if engine.is_threefold_repetition() || engine.is_fifty_move_rule() {
+            return 0.0
+        }
+        // So do the variant's own ends, which can happen before the last ply of the search
+        if let Some(score) = variant_score(engine) {
+            return score
+        }
+        let curr_board_key = engine.board.position_hash();
         // If the move is in the transposition table, we return it
         let relative_depht = max_depht - depht;
         let transpo = transposition_table.get(&curr_board_key).cloned();
@@ -203,52 +223,32 @@ impl MiniMaxAi {
         }
         // If we are at max depht, we return the position evaluation
         if depht == max_depht {
-            let eval = self.eval_position(&engine.board);
+            let eval = engine.variant.evaluation(&engine.board, self.eval_position(&engine.board));
             //transposition_table.insert((curr_board_key, relative_depht), eval);
             return eval
         }
         
-        // Otherwise we keep searching the tree
-        let possible_moves = engine.gen_all_moves();
-
-        let mut new_engines: Vec<_> = possible_moves.into_iter().map(|m| {
-            let mut new_engine = engine.clone();
-            new_engine.play_once(m);
-            new_engine
-        }).collect();
-
-        // Sketchy move ordering to improve alpha beta pruning
-        // new_engines.sort_by(|m1, m2| {
-        //     let m1s = transposition_table.get(&m1.get_board_as_key()).unwrap_or(&(0usize, 0.0)).1;
-        //     let m2s = transposition_table.get(&m2.get_board_as_key()).unwrap_or(&(0usize, 0.0)).1;
-        //     let ord = m1s.total_cmp(&m2s);
-        //     match !is_max {
-        //         true => ord,
-        //         false => ord.reverse()
-        //     }
-        // });
-        
-        // alternative end conditions, checkmate or draw
-        if new_engines.len() == 0 {
-            // If checkmate
-            if engine.check {
-                return match engine.current_player {
-                    Color::Black => f64::INFINITY,
-                    Color::White => -f64::INFINITY
-                }
-            }
-            // if draw (pat, no moves available and not in check)
-            else {
-                return 0.0
-            }
+        // Otherwise we keep searching the tree, every promotion piece being a child of its own
+        let mut possible_moves = engine.gen_all_moves();
+        order_moves(&mut possible_moves);
+
+        // The variant tells whether the game ends here, and who won it
+        // Draws with moves left are scored by the evaluation, material still being worth winning
+        match engine.variant.outcome(engine, &possible_moves).and_then(|outcome| outcome.winner()) {
+            Some(Color::White) => return f64::INFINITY,
+            Some(Color::Black) => return -f64::INFINITY,
+            None if possible_moves.is_empty() => return 0.0,
+            None => {}
         }
         
         let mut curr_val = init_node_eval(is_max);
-        for mut new_engine in new_engines {
-            new_engine.finish_turn();
-            new_engine.prepare_new_turn();
+        for m in possible_moves {
+            let Ok(undo) = engine.make_move(&m) else {
+                continue
+            };
+            let next_eval = self.mini_max(engine, depht + 1, max_depht, !is_max, alpha, beta, transposition_table, called);
+            engine.unmake_move(undo);
             if is_max {
-                let next_eval = self.mini_max(&mut new_engine, depht + 1, max_depht, !is_max, alpha, beta, transposition_table, called);
                 if next_eval >= curr_val {
                     curr_val = next_eval;
                 }
@@ -258,7 +258,6 @@ impl MiniMaxAi {
                 alpha = std::cmp::max_by(alpha, curr_val, |a, b| a.total_cmp(b));
             }
             else {
-                let next_eval = self.mini_max(&mut new_engine, depht + 1, max_depht, !is_max, alpha, beta, transposition_table, called);
                 if next_eval <= curr_val {
                     curr_val = next_eval;
                 }
@@ -279,28 +278,29 @@ impl MiniMaxAi {
 }
 
 impl Ai for MiniMaxAi {
-    fn play(&mut self, engine: &GameEngine) -> Vec<Move> {
-        let mut ai_moves = vec!();
+    fn play(&mut self, engine: &GameEngine) -> Option<Move> {
         let moves_to_evaluate = engine.gen_all_moves();
         if moves_to_evaluate.len() == 0 {
-            return ai_moves;
+            return None;
         }
+        let mult = match self.machine_player {
+            Color::Black => -1.0,
+            Color::White => 1.0
+        };
         let t = std::time::Instant::now();
         let scores: Vec<_> = moves_to_evaluate
             .par_iter()
-            .map(|m| {
-                let mut evaluation_engine = engine.clone();
-                evaluation_engine.play_once(m.clone());
-                evaluation_engine.finish_turn();
-                evaluation_engine.prepare_new_turn();
-                let evaluation = self.mini_max_iterative_deepening(&mut evaluation_engine);
+            // Each worker searches on its own engine, moves are played and taken back on it
+            .map_init(|| engine.clone(), |evaluation_engine, m| {
+                // A move the board refuses is never picked
+                let Ok(undo) = evaluation_engine.make_move(m) else {
+                    return (-mult * f64::INFINITY, 0)
+                };
+                let evaluation = self.mini_max_iterative_deepening(evaluation_engine);
+                evaluation_engine.unmake_move(undo);
                 evaluation
             })
             .collect();
-        let mult = match self.machine_player {
-            Color::Black => -1.0,
-            Color::White => 1.0
-        };
         let mut total_called = 0;
         let best_move = scores
             .iter()
@@ -334,8 +334,7 @@ impl Ai for MiniMaxAi {
             elapsed.as_secs(),
             total_called as f64 / elapsed.as_secs_f64(),
         );
-        ai_moves.push(moves_to_evaluate[best_move.0].clone());
-        ai_moves
+        moves_to_evaluate.get(best_move.0).cloned()
     }
 
     fn eval_position(&self, board: &ChessBoard) -> f64 {
@@ -369,4 +368,21 @@ impl Ai for MiniMaxAi {
     fn set_depht(&mut self, depht: usize) {
         self.depth = depht;
     }
-}
\ No newline at end of file
+
+    fn name(&self) -> String {
+        format!("MiniMaxAi (depth {})", self.depth)
+    }
+}
+
+
+#[test]
+fn test_mini_max_promotion() {
+    // d8=N checks the b7 king and forks the f7 queen, which is won next move, while d8=Q gives no check and wins nothing
+    let engine = GameEngine::from_fen("8/1k1P1q2/8/8/8/8/8/K7 w - - 0 1").unwrap();
+    let mut ai = MiniMaxAi::new(Color::White);
+    assert_eq!(ai.play(&engine), Some(Move::Promote((1, 3), (0, 3), CanPromoteTo::Knight)));
+    // The greedy ai queens right away
+    let engine = GameEngine::from_fen("8/1P6/8/8/8/8/k7/7K w - - 0 1").unwrap();
+    let mut ai = BestPlayDephtOneAi::new(Color::White);
+    assert_eq!(ai.play(&engine), Some(Move::Promote((1, 1), (0, 1), CanPromoteTo::Queen)));
+}
@@ -0,0 +1,626 @@
+use std::collections::HashSet;
+use std::sync::OnceLock;
+
+use crate::{
+    chessbord::ChessBoard,
+    piece::{Color, Move, Piece, PieceType, Position, PROMOTIONS},
+};
+
+// One bit per square, the square index being row * 8 + col (a8 = 0, h1 = 63)
+pub type Bitboard = u64;
+
+pub fn square(pos: &Position) -> usize {
+    (pos.0 * 8 + pos.1) as usize
+}
+
+pub fn position(square: usize) -> Position {
+    ((square / 8) as i8, (square % 8) as i8)
+}
+
+pub fn bit(pos: &Position) -> Bitboard {
+    1 << square(pos)
+}
+
+// Iterates over the squares of a bitboard, lowest index first
+pub fn squares(mut bb: Bitboard) -> impl Iterator<Item = usize> {
+    std::iter::from_fn(move || {
+        if bb == 0 {
+            return None
+        }
+        let sq = bb.trailing_zeros() as usize;
+        bb &= bb - 1;
+        Some(sq)
+    })
+}
+
+pub(crate) fn color_index(color: &Color) -> usize {
+    match color {
+        Color::White => 0,
+        Color::Black => 1
+    }
+}
+
+// Empty squares have no bitboard
+fn type_index(ptype: &PieceType) -> Option<usize> {
+    match ptype {
+        PieceType::Pawn => Some(0),
+        PieceType::Knight => Some(1),
+        PieceType::Bishop => Some(2),
+        PieceType::Rook => Some(3),
+        PieceType::Queen => Some(4),
+        PieceType::King => Some(5),
+        PieceType::Empty => None
+    }
+}
+
+const COLORS: [Color; 2] = [Color::White, Color::Black];
+const TYPES: [PieceType; 6] = [
+    PieceType::Pawn,
+    PieceType::Knight,
+    PieceType::Bishop,
+    PieceType::Rook,
+    PieceType::Queen,
+    PieceType::King,
+];
+
+// The first four directions go towards lower square indexes, the last four towards higher ones
+const DIRECTIONS: [(i8, i8); 8] = [(-1, -1), (-1, 0), (-1, 1), (0, -1), (0, 1), (1, -1), (1, 0), (1, 1)];
+const ROOK_DIRECTIONS: [usize; 4] = [1, 3, 4, 6];
+const BISHOP_DIRECTIONS: [usize; 4] = [0, 2, 5, 7];
+const KNIGHT_JUMPS: [(i8, i8); 8] = [(-2, -1), (-2, 1), (-1, -2), (-1, 2), (1, -2), (1, 2), (2, -1), (2, 1)];
+
+// Found once by trying sparse random numbers against every blocker set of the square
+const ROOK_MAGICS: [u64; 64] = [
+    0x2180_0020_4000_8014, 0x0180_2000_8140_0016, 0x0100_2000_1009_0040, 0x1100_0a04_2100_1000,
+    0x0200_0200_1008_0421, 0x0080_0200_8004_0001, 0x0400_1001_2200_8428, 0x0100_0200_4020_8100,
+    0x0000_8000_c011_2680, 0x0010_4000_2010_0040, 0x0000_8010_0020_0080, 0x14a1_0020_1001_000c,
+    0x2801_0010_0800_0500, 0x1101_8004_0112_0080, 0x0011_0001_0002_0084, 0x7ec1_0011_0000_805a,
+    0x4440_0080_0040_9024, 0x8090_8100_2040_0102, 0x0040_2200_1200_4082, 0x0001_0100_2010_000b,
+    0x0048_8080_0800_0400, 0x8002_0080_8002_0400, 0x0000_0400_0102_9058, 0x0125_0200_040c_8061,
+    0x0000_4004_8000_8028, 0x0010_2008_c000_5000, 0x0020_1000_8080_2000, 0x0031_d001_000a_2100,
+    0x0000_0400_8008_0080, 0x0040_0200_8080_0400, 0x0602_1254_0010_2801, 0x2010_0042_0004_0081,
+    0x0020_2240_0180_0080, 0x0000_2010_0840_0040, 0x0008_1041_0100_2000, 0x1220_8010_0080_0800,
+    0x5408_0011_0100_0804, 0x0004_0100_4040_0200, 0x4080_0802_0400_4110, 0x0000_1045_0200_02a4,
+    0xc100_8020_4000_8003, 0x1c90_0020_0040_4010, 0x8220_0010_0084_8020, 0x0211_0008_1001_0020,
+    0x0020_0400_0800_8080, 0x804a_0028_0406_0010, 0x0201_0150_4244_0028, 0x2001_2058_8402_0009,
+    0x1000_4211_2681_0200, 0x0018_4004_8020_1280, 0x4801_0010_4020_0100, 0x40b8_0810_0021_0500,
+    0x0001_0040_8020_1002, 0x0044_8004_0002_0180, 0x8812_0128_0250_4400, 0x2280_0080_4409_0a00,
+    0x0185_0025_4212_0082, 0x2001_8026_d340_0101, 0x0a10_8008_2012_0042, 0x4001_0014_2030_004b,
+    0x8002_0010_0408_2002, 0x0012_0004_3008_01a2, 0x1010_0108_7000_8204, 0x2c08_0048_2081_0402,
+];
+const BISHOP_MAGICS: [u64; 64] = [
+    0x0040_0288_0040_8080, 0xb010_5400_c28a_020a, 0x0010_0440_4840_4030, 0x5009_1c01_050c_4422,
+    0x0082_0210_0000_9080, 0x0002_0131_0802_0502, 0x1000_8848_0210_0200, 0x0201_0041_1001_1014,
+    0x006a_4230_2202_0048, 0x0082_0550_0082_0088, 0x0200_2464_1086_0700, 0x00d9_4220_8200_2000,
+    0x0800_8450_4000_9001, 0x0080_0828_0410_0444, 0x0488_8048_0888_0820, 0x0010_0049_0801_3040,
+    0x0040_1020_0801_0120, 0x2010_0021_8400_9085, 0x2002_0004_0024_0900, 0x4108_0210_8203_4002,
+    0x0042_0204_2201_00d0, 0x8842_0042_4110_1101, 0x9044_0002_0201_8440, 0x0002_0c00_4202_0101,
+    0x0220_0440_08c8_080a, 0x3644_0200_1050_0102, 0x1158_0200_8104_0100, 0x0020_0800_e280_6040,
+    0x0210_1040_6400_4040, 0x0a20_4080_0300_a020, 0x0202_2140_0a07_1080, 0x081a_2088_0044_0080,
+    0x0028_0841_0048_a220, 0x1004_1120_0804_1402, 0x0500_1802_0204_0401, 0x0c00_4008_2102_0200,
+    0x0602_1208_0414_0040, 0x0010_0912_0108_4048, 0x0801_0842_0a80_8200, 0x4808_0109_0004_20a0,
+    0xa028_1410_8800_0408, 0x00d2_1202_0202_a011, 0x010a_0014_4402_0800, 0x0054_8020_1800_0101,
+    0x4008_2004_1400_2241, 0x0020_0c04_9020_0202, 0x0108_0801_0408_0040, 0x0088_0805_0054_0024,
+    0x0218_4208_0442_0442, 0x0080_2202_1004_0080, 0x6700_0100_8804_4015, 0x0000_a020_8404_0000,
+    0x8280_0204_0504_0050, 0x0080_0460_0491_0420, 0x2011_0202_0802_0024, 0x1408_a108_0220_4002,
+    0x0802_002a_1802_0810, 0x6040_0080_4410_0500, 0x0044_8002_0201_0420, 0x0000_0040_6084_0401,
+    0xa000_8001_3002_0888, 0x1100_0004_2048_0a40, 0x8090_0410_5001_1104, 0x0002_0202_1202_0208,
+];
+
+// Sliding attacks of a square are looked up by multiplying its relevant blockers by a magic number,
+// the top bits of the product indexing its slice of the attack table
+#[derive(Clone, Copy, Default)]
+struct Magic {
+    // The squares whose occupancy changes the attacks, the board edges excluded
+    mask: Bitboard,
+    magic: u64,
+    shift: u32,
+    offset: usize,
+}
+
+impl Magic {
+    fn index(&self, occupied: Bitboard) -> usize {
+        self.offset + ((occupied & self.mask).wrapping_mul(self.magic) >> self.shift) as usize
+    }
+}
+
+struct Tables {
+    knight: [Bitboard; 64],
+    king: [Bitboard; 64],
+    // Squares attacked by a pawn of each color
+    pawn: [[Bitboard; 64]; 2],
+    rays: [[Bitboard; 64]; 8],
+    // Squares strictly between two aligned squares
+    between: Vec<[Bitboard; 64]>,
+    rook_magics: [Magic; 64],
+    bishop_magics: [Magic; 64],
+    // The attacks of every magic, each one using its own slice
+    sliding: Vec<Bitboard>,
+}
+
+fn offset(sq: usize, (dr, dc): (i8, i8)) -> Option<Position> {
+    let (row, col) = position(sq);
+    let to = (row + dr, col + dc);
+    match (0..8).contains(&to.0) && (0..8).contains(&to.1) {
+        true => Some(to),
+        false => None
+    }
+}
+
+fn tables() -> &'static Tables {
+    static TABLES: OnceLock<Tables> = OnceLock::new();
+    TABLES.get_or_init(|| {
+        let mut tables = Tables {
+            knight: [0; 64],
+            king: [0; 64],
+            pawn: [[0; 64]; 2],
+            rays: [[0; 64]; 8],
+            between: vec![[0; 64]; 64],
+            rook_magics: [Magic::default(); 64],
+            bishop_magics: [Magic::default(); 64],
+            sliding: vec!(),
+        };
+        for sq in 0..64 {
+            for jump in KNIGHT_JUMPS {
+                tables.knight[sq] |= offset(sq, jump).map_or(0, |to| bit(&to));
+            }
+            for (d, dir) in DIRECTIONS.iter().enumerate() {
+                tables.king[sq] |= offset(sq, *dir).map_or(0, |to| bit(&to));
+                let mut ray = 0;
+                let mut current = sq;
+                while let Some(to) = offset(current, *dir) {
+                    tables.between[sq][square(&to)] = ray;
+                    ray |= bit(&to);
+                    current = square(&to);
+                }
+                tables.rays[d][sq] = ray;
+            }
+            // White pawns go up the board (towards row 0), black ones down
+            for (c, dr) in [(0, -1), (1, 1)] {
+                for dc in [-1, 1] {
+                    tables.pawn[c][sq] |= offset(sq, (dr, dc)).map_or(0, |to| bit(&to));
+                }
+            }
+        }
+        for sq in 0..64 {
+            tables.rook_magics[sq] = init_magic(&tables.rays, sq, &ROOK_DIRECTIONS, ROOK_MAGICS[sq], &mut tables.sliding);
+            tables.bishop_magics[sq] = init_magic(&tables.rays, sq, &BISHOP_DIRECTIONS, BISHOP_MAGICS[sq], &mut tables.sliding);
+        }
+        tables
+    })
+}
+
+// Walks the rays up to their first blocker, only used to fill the magic tables
+fn ray_attacks(rays: &[[Bitboard; 64]; 8], sq: usize, directions: &[usize], occupied: Bitboard) -> Bitboard {
+    directions.iter().fold(0, |attacks, d| {
+        let ray = rays[*d][sq];
+        let blockers = ray & occupied;
+        if blockers == 0 {
+            return attacks | ray
+        }
+        let nearest = match *d < 4 {
+            true => 63 - blockers.leading_zeros() as usize,
+            false => blockers.trailing_zeros() as usize
+        };
+        attacks | (ray ^ rays[*d][nearest])
+    })
+}
+
+// Fills the square's slice of the attack table for every set of blockers the mask allows
+fn init_magic(rays: &[[Bitboard; 64]; 8], sq: usize, directions: &[usize], magic: u64, sliding: &mut Vec<Bitboard>) -> Magic {
+    // The last square of a ray stops it whether it is occupied or not
+    let mask = directions.iter().fold(0, |mask, d| {
+        let ray = rays[*d][sq];
+        let edge = match *d < 4 {
+            true => ray & ray.wrapping_neg(),
+            false => (1u64 << 63).checked_shr(ray.leading_zeros()).unwrap_or(0)
+        };
+        mask | (ray & !edge)
+    });
+    let magic = Magic { mask, magic, shift: 64 - mask.count_ones(), offset: sliding.len() };
+    sliding.resize(sliding.len() + (1 << mask.count_ones()), 0);
+    let mut blockers: Bitboard = 0;
+    loop {
+        sliding[magic.index(blockers)] = ray_attacks(rays, sq, directions, blockers);
+        blockers = blockers.wrapping_sub(mask) & mask;
+        if blockers == 0 {
+            return magic
+        }
+    }
+}
+
+pub fn rook_attacks(sq: usize, occupied: Bitboard) -> Bitboard {
+    let tables = tables();
+    tables.sliding[tables.rook_magics[sq].index(occupied)]
+}
+
+pub fn bishop_attacks(sq: usize, occupied: Bitboard) -> Bitboard {
+    let tables = tables();
+    tables.sliding[tables.bishop_magics[sq].index(occupied)]
+}
+
+pub fn between(from: usize, to: usize) -> Bitboard {
+    tables().between[from][to]
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Bitboards {
+    pub pieces: [[Bitboard; 6]; 2],
+    pub occupancy: [Bitboard; 2],
+}
+
+impl Bitboards {
+    pub fn from_board(board: &[Vec<Piece>]) -> Self {
+        let mut bitboards = Self::default();
+        for (i, row) in board.iter().enumerate() {
+            for (j, piece) in row.iter().enumerate() {
+                bitboards.put(&(i as i8, j as i8), piece);
+            }
+        }
+        bitboards
+    }
+
+    fn toggle(&mut self, pos: &Position, piece: &Piece) {
+        if let (Some(color), Some(ptype)) = (piece.color(), piece.get_type()) {
+            self.toggle_pieces(&color, &ptype, bit(pos));
+            self.occupancy[color_index(&color)] ^= bit(pos);
+        }
+    }
+
+    // Flips the squares of the mask in the bitboard of that type, nothing to flip for empty squares
+    fn toggle_pieces(&mut self, color: &Color, ptype: &PieceType, mask: Bitboard) {
+        if let Some(t) = type_index(ptype) {
+            self.pieces[color_index(color)][t] ^= mask;
+        }
+    }
+
+    pub fn put(&mut self, pos: &Position, piece: &Piece) {
+        self.toggle(pos, piece)
+    }
+
+    pub fn remove(&mut self, pos: &Position, piece: &Piece) {
+        self.toggle(pos, piece)
+    }
+
+    // Empties every square of the mask
+    pub fn clear(&mut self, mask: Bitboard) {
+        for pieces in self.pieces.iter_mut().flatten() {
+            *pieces &= !mask;
+        }
+        for occupancy in &mut self.occupancy {
+            *occupancy &= !mask;
+        }
+    }
+
+    pub fn pieces(&self, color: &Color, ptype: &PieceType) -> Bitboard {
+        type_index(ptype).map_or(0, |t| self.pieces[color_index(color)][t])
+    }
+
+    pub fn occupancy(&self, color: &Color) -> Bitboard {
+        self.occupancy[color_index(color)]
+    }
+
+    pub fn occupied(&self) -> Bitboard {
+        self.occupancy[0] | self.occupancy[1]
+    }
+
+    pub fn piece_at(&self, sq: usize) -> Option<(Color, PieceType)> {
+        for (c, color) in COLORS.iter().enumerate() {
+            if self.occupancy[c] & (1 << sq) == 0 {
+                continue
+            }
+            for (t, ptype) in TYPES.iter().enumerate() {
+                if self.pieces[c][t] & (1 << sq) != 0 {
+                    return Some((color.clone(), ptype.clone()))
+                }
+            }
+        }
+        None
+    }
+
+    pub fn king_square(&self, color: &Color) -> Option<usize> {
+        match self.pieces(color, &PieceType::King) {
+            0 => None,
+            king => Some(king.trailing_zeros() as usize)
+        }
+    }
+
+    // Squares a piece standing on `sq` attacks, whatever stands on them
+    pub fn attacks(sq: usize, color: &Color, ptype: &PieceType, occupied: Bitboard) -> Bitboard {
+        match ptype {
+            PieceType::Pawn => tables().pawn[color_index(color)][sq],
+            PieceType::Knight => tables().knight[sq],
+            PieceType::Bishop => bishop_attacks(sq, occupied),
+            PieceType::Rook => rook_attacks(sq, occupied),
+            PieceType::Queen => bishop_attacks(sq, occupied) | rook_attacks(sq, occupied),
+            PieceType::King => tables().king[sq],
+            PieceType::Empty => 0
+        }
+    }
+
+    // Pieces of color `by` attacking `sq`
+    pub fn attackers(&self, sq: usize, by: &Color, occupied: Bitboard) -> Bitboard {
+        let c = color_index(by);
+        let queens = self.pieces(by, &PieceType::Queen);
+        let diagonal = self.pieces(by, &PieceType::Bishop) | queens;
+        let line = self.pieces(by, &PieceType::Rook) | queens;
+        // A pawn of color `by` attacks `sq` from where a pawn of the other color on `sq` would attack
+        (tables().pawn[1 - c][sq] & self.pieces(by, &PieceType::Pawn))
+            | (tables().knight[sq] & self.pieces(by, &PieceType::Knight))
+            | (tables().king[sq] & self.pieces(by, &PieceType::King))
+            | (bishop_attacks(sq, occupied) & diagonal)
+            | (rook_attacks(sq, occupied) & line)
+    }
+
+    pub fn is_attacked(&self, sq: usize, by: &Color) -> bool {
+        self.attackers(sq, by, self.occupied()) != 0
+    }
+
+    // Every square attacked by a faction, sliders see through the enemy king so it can't step back along their line
+    pub fn controlled(&self, color: &Color) -> Bitboard {
+        let occupied = self.occupied() & !self.pieces(&color.other(), &PieceType::King);
+        let c = color_index(color);
+        let mut controlled = 0;
+        for (t, ptype) in TYPES.iter().enumerate() {
+            for sq in squares(self.pieces[c][t]) {
+                controlled |= Self::attacks(sq, color, ptype, occupied);
+            }
+        }
+        controlled
+    }
+
+    // What a faction sees for Fog of war: its own squares, the ones its pieces attack and the ones its pawns can push to
+    pub fn visible(&self, color: &Color, en_passant: Option<Position>) -> Bitboard {
+        let own = self.occupancy(color);
+        let c = color_index(color);
+        let mut visible = own;
+        for (t, ptype) in TYPES.iter().enumerate() {
+            for sq in squares(self.pieces[c][t]) {
+                visible |= Self::attacks(sq, color, ptype, self.occupied());
+            }
+        }
+        self.moves_onto(color, en_passant, !own)
+            .iter()
+            .filter_map(Move::to)
+            .fold(visible, |acc, to| acc | bit(&to))
+    }
+
+    // Moves that follow the piece rules but may leave the own king in check, castling excepted
+    pub fn pseudo_legal_moves(&self, color: &Color, en_passant: Option<Position>) -> Vec<Move> {
+        // Kings are never taken, checks are dealt with before
+        let targets = !self.occupancy(color) & !self.pieces(&color.other(), &PieceType::King);
+        self.moves_onto(color, en_passant, targets)
+    }
+
+    // The same moves, restricted to the `targets` squares (pawn pushes excepted)
+    pub fn moves_onto(&self, color: &Color, en_passant: Option<Position>, targets: Bitboard) -> Vec<Move> {
+        let mut moves = vec!();
+        let own = self.occupancy(color);
+        let enemy = self.occupancy(&color.other());
+        let occupied = own | enemy;
+        let to_move = |from: usize, to: usize| match enemy & (1 << to) {
+            0 => Move::Move(position(from), position(to)),
+            _ => Move::Take(position(from), position(to))
+        };
+
+        let (push, start_row, last_row) = match color {
+            Color::White => (-8, 6, 0),
+            Color::Black => (8, 1, 7)
+        };
+        // Reaching the last row gives one move per promotion piece
+        let mut pawn_moves = |m: Move| match m.to() {
+            Some(to) if to.0 == last_row => {
+                let (from, to) = (m.from().unwrap(), to);
+                moves.extend(PROMOTIONS.iter().map(|p| Move::Promote(from, to, p.clone())));
+            },
+            _ => moves.push(m)
+        };
+        for from in squares(self.pieces(color, &PieceType::Pawn)) {
+            let one = (from as i32 + push) as usize;
+            if occupied & (1 << one) == 0 {
+                pawn_moves(Move::Move(position(from), position(one)));
+                let two = (one as i32 + push) as usize;
+                if position(from).0 == start_row && occupied & (1 << two) == 0 {
+                    pawn_moves(Move::Move(position(from), position(two)));
+                }
+            }
+            let attacks = tables().pawn[color_index(color)][from];
+            for to in squares(attacks & enemy & targets) {
+                pawn_moves(Move::Take(position(from), position(to)));
+            }
+            if let Some(ep) = en_passant {
+                if attacks & bit(&ep) != 0 {
+                    pawn_moves(Move::EnPassant(position(from), ep));
+                }
+            }
+        }
+
+        for ptype in &TYPES[1..] {
+            for from in squares(self.pieces(color, ptype)) {
+                for to in squares(Self::attacks(from, color, ptype, occupied) & targets) {
+                    moves.push(to_move(from, to));
+                }
+            }
+        }
+        moves
+    }
+
+    // The bitboards once a move (castling excepted) is played
+    pub fn after_move(&self, m: &Move) -> Self {
+        let mut after = *self;
+        if let Move::Drop(color, ptype, to) = m {
+            after.toggle_pieces(color, ptype, bit(to));
+            after.occupancy[color_index(color)] |= bit(to);
+            return after
+        }
+        let (from, to) = match m {
+            Move::Move(from, to) | Move::Take(from, to) | Move::EnPassant(from, to) | Move::Promote(from, to, _) => (square(from), square(to)),
+            _ => return after
+        };
+        if let Some((color, ptype)) = self.piece_at(to) {
+            after.toggle_pieces(&color, &ptype, 1 << to);
+            after.occupancy[color_index(&color)] ^= 1 << to;
+        }
+        if let Move::EnPassant(from_pos, to_pos) = m {
+            after.clear(bit(&(from_pos.0, to_pos.1)));
+        }
+        if let Some((color, ptype)) = self.piece_at(from) {
+            let moved = (1 << from) | (1 << to);
+            after.toggle_pieces(&color, &ptype, moved);
+            after.occupancy[color_index(&color)] ^= moved;
+            if let Move::Promote(_, _, p) = m {
+                after.toggle_pieces(&color, &ptype, 1 << to);
+                after.toggle_pieces(&color, &p.clone().into(), 1 << to);
+            }
+        }
+        after
+    }
+
+    // The move doesn't leave the own king attacked
+    pub fn is_legal(&self, m: &Move, color: &Color) -> bool {
+        let after = self.after_move(m);
+        match after.king_square(color) {
+            Some(king) => !after.is_attacked(king, &color.other()),
+            None => true
+        }
+    }
+}
+
+impl ChessBoard {
+    // The squares of each attack on the king of `player`, checker included
+    pub fn attack_vectors(&self, player: &Color) -> Vec<HashSet<Position>> {
+        let king = match self.bitboards.king_square(player) {
+            Some(king) => king,
+            None => return vec!()
+        };
+        let checkers = self.bitboards.attackers(king, &player.other(), self.bitboards.occupied());
+        squares(checkers)
+            .map(|checker| squares(between(checker, king) | (1 << checker)).map(position).collect())
+            .collect()
+    }
+
+    // Crazyhouse drops: anywhere empty but pawns on the first and last ranks, only in between a single checker and the king
+    pub fn drop_moves(&self, player: &Color) -> Vec<Move> {
+        let pocket = match self.pockets {
+            Some(pockets) => pockets[color_index(player)],
+            None => return vec!()
+        };
+        let attack_vectors = self.attack_vectors(player);
+        let mut targets = !self.bitboards.occupied();
+        match attack_vectors.as_slice() {
+            [] => {},
+            [attack] => targets &= attack.iter().fold(0, |acc, pos| acc | bit(pos)),
+            _ => return vec!()
+        }
+        let mut moves = vec!();
+        for ptype in pocket.pieces() {
+            let allowed = match ptype {
+                PieceType::Pawn => targets & !(0xff | 0xff << 56),
+                _ => targets
+            };
+            moves.extend(squares(allowed).map(|sq| Move::Drop(player.clone(), ptype.clone(), position(sq))));
+        }
+        moves
+    }
+
+    pub fn visible_squares(&self, player: &Color) -> Bitboard {
+        self.bitboards.visible(player, self.en_passant_target(player))
+    }
+
+    // Where a pawn of `player` would land by taking en passant
+    pub fn en_passant_target(&self, player: &Color) -> Option<Position> {
+        let target = self.en_passant?;
+        let row = match player {
+            Color::White => 2,
+            Color::Black => 5
+        };
+        if target.0 == row { Some(target) } else { None }
+    }
+
+    pub fn castling_moves(&self, player: &Color) -> Vec<Move> {
+        self.castles(player, true)
+    }
+
+    // Fog of war castles whatever the king goes through, its player can't tell where the enemy attacks
+    pub fn castling_moves_through_checks(&self, player: &Color) -> Vec<Move> {
+        self.castles(player, false)
+    }
+
+    fn castles(&self, player: &Color, safe: bool) -> Vec<Move> {
+        let mut moves = vec!();
+        let king_sq = match self.bitboards.king_square(player) {
+            Some(sq) => sq,
+            None => return moves
+        };
+        let them = player.other();
+        if safe && self.bitboards.is_attacked(king_sq, &them) {
+            return moves
+        }
+        for kingside in [true, false] {
+            if !self.castling_rights.has(player, kingside) {
+                continue
+            }
+            if self.castling_pieces(player, kingside).is_none() {
+                continue
+            }
+            let (king_from, king_to, rook_from, rook_to) = match self.castling_squares(player, kingside) {
+                Ok(squares) => squares,
+                Err(_) => continue
+            };
+            // Both paths must be free of any other piece, and the king can't pass through check
+            let (from_sq, to_sq, rook_sq) = (square(&king_from), square(&king_to), square(&rook_from));
+            let castlers = bit(&king_from) | bit(&rook_from);
+            let king_path = between(from_sq, to_sq) | bit(&king_to);
+            let rook_path = between(rook_sq, square(&rook_to)) | bit(&rook_to);
+            let occupied = self.bitboards.occupied() & !castlers;
+            if occupied & (king_path | rook_path) != 0 {
+                continue
+            }
+            // The rook leaving may uncover the king's square along the back rank
+            if safe && squares(king_path).any(|sq| self.bitboards.attackers(sq, &them, occupied) != 0) {
+                continue
+            }
+            moves.push(match kingside {
+                true => Move::KingsideCastle(player.clone()),
+                false => Move::QueensideCastle(player.clone())
+            });
+        }
+        moves
+    }
+}
+
+
+#[test]
+fn test_bitboard_moves() {
+    let kiwipete = ChessBoard::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").unwrap();
+    assert_eq!(kiwipete.gen_all_moves(&Color::White).len(), 48);
+    assert_eq!(kiwipete.castling_moves(&Color::White).len(), 2);
+    // The e4 pawn can't take en passant on d3, its king on a4 would be left facing the queen along the fourth rank
+    let pinned = ChessBoard::from_fen("8/8/8/8/k2Pp2Q/8/8/3K4 b - d3 0 1").unwrap();
+    assert!(!pinned.gen_all_moves(&Color::Black).iter().any(|m| matches!(m, Move::EnPassant(_, _))));
+    // The a7 pawn promotes to each piece both pushing to a8 and taking on b8, nothing else moves it
+    let promotion = ChessBoard::from_fen("1n2k3/P7/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+    let pawn_moves: Vec<Move> = promotion.gen_all_moves(&Color::White).into_iter().filter(|m| m.from() == Some((1, 0))).collect();
+    assert_eq!(pawn_moves.len(), 8);
+    for to in [(0, 0), (0, 1)] {
+        let pieces: Vec<_> = pawn_moves.iter().filter_map(|m| match m {
+            Move::Promote(_, t, p) if *t == to => Some(p.clone()),
+            _ => None
+        }).collect();
+        assert_eq!(pieces, PROMOTIONS.to_vec());
+    }
+    assert_eq!(rook_attacks(square(&(7, 0)), bit(&(7, 3)) | bit(&(4, 0))), 0x0E01_0101_0000_0000);
+    assert_eq!(between(square(&(0, 0)), square(&(7, 7))).count_ones(), 6);
+    // The magic lookups agree with walking the rays
+    use rand::{Rng, SeedableRng, rngs::StdRng};
+    let mut rng = StdRng::seed_from_u64(7);
+    for _ in 0..50 {
+        let occupied = rng.gen::<u64>() & rng.gen::<u64>();
+        for sq in 0..64 {
+            assert_eq!(rook_attacks(sq, occupied), ray_attacks(&tables().rays, sq, &ROOK_DIRECTIONS, occupied));
+            assert_eq!(bishop_attacks(sq, occupied), ray_attacks(&tables().rays, sq, &BISHOP_DIRECTIONS, occupied));
+        }
+    }
+    // Empty squares make up no bitboard of their own
+    assert_eq!(kiwipete.bitboards.pieces(&Color::White, &PieceType::Empty), 0);
+}
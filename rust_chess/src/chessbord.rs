@@ -1,6 +1,9 @@
 use std::collections::{HashMap, HashSet};
 
-use crate::piece::{Color, CanPromoteTo, AttackVector};
+use crate::piece::{Color, CanPromoteTo};
+use crate::bitboard::{self, Bitboard, Bitboards, color_index};
+use crate::error::ChessError;
+use crate::zobrist::Zobrist;
 
 use super::piece::{
     Piece,
@@ -31,7 +34,10 @@ pub struct PieceRepr {
 pub struct CellRepr {
     pub piece: PieceRepr,
     pub threatened: bool,
-    pub controll: Option<String>
+    pub controll: Option<String>,
+    // Out of sight of the viewing player, nothing else about the square is sent
+    #[serde(default)]
+    pub hidden: bool
 }
 
 // #[derive(Default, Debug, Serialize, Deserialize, Clone)]
@@ -56,6 +62,22 @@ pub fn empty_board() -> Vec<Vec<Piece>> {
     ).collect()
 }
 
+pub const ORTHODOX_CASTLING_FILES: [[i8; 2]; 2] = [[0, 7], [0, 7]];
+
+pub(crate) fn back_rank(color: &Color) -> i8 {
+    match color {
+        Color::White => 7,
+        Color::Black => 0
+    }
+}
+
+pub(crate) fn pawn_start_rank(color: &Color) -> i8 {
+    match color {
+        Color::White => 6,
+        Color::Black => 1
+    }
+}
+
 pub fn initial_board() -> Vec<Vec<Piece>> {
     let mut board = empty_board();
     // Black faction
@@ -63,14 +85,14 @@ pub fn initial_board() -> Vec<Vec<Piece>> {
     board[0][0] = Piece::Rook(Rook::new((0, 0), Color::Black, 1));
     board[0][1] = Piece::Knight(Knight::new((0, 1), Color::Black, 2));
     board[0][2] = Piece::Bishop(Bishop::new((0, 2), Color::Black, 3));
-    board[0][3] = Piece::King(King::new((0, 3), Color::Black, 4));
-    board[0][4] = Piece::Queen(Queen::new((0, 4), Color::Black, 5));
+    board[0][3] = Piece::Queen(Queen::new((0, 3), Color::Black, 4));
+    board[0][4] = Piece::King(King::new((0, 4), Color::Black, 5));
     board[0][5] = Piece::Bishop(Bishop::new((0, 5), Color::Black, 6));
     board[0][6] = Piece::Knight(Knight::new((0, 6), Color::Black, 7));
     board[0][7] = Piece::Rook(Rook::new((0, 7), Color::Black, 8));
     // Pawns
     for i in 0..8 {
-        board[1][i] = Piece::Pawn(Pawn::new((1, i as i8), Color::Black, i * 30));
+        board[1][i] = Piece::Pawn(Pawn::new((1, i as i8), Color::Black, 17 + i));
     }
 
     // White faction
@@ -85,7 +107,7 @@ pub fn initial_board() -> Vec<Vec<Piece>> {
     board[7][7] = Piece::Rook(Rook::new((7, 7), Color::White, 16));
     //Pawns
     for i in 0..8 {
-        board[6][i] = Piece::Pawn(Pawn::new((6, i as i8), Color::White, i * 40));
+        board[6][i] = Piece::Pawn(Pawn::new((6, i as i8), Color::White, 25 + i));
     }
 
     board
@@ -113,10 +135,15 @@ pub fn apply_markers(board: &mut WebappRepr, moves: &Vec<Move>) {
                 let to = m.to().unwrap();
                 board[to.0 as usize][to.1 as usize].piece.idx = Some(6);
             }
-            Move::Invalid => {
-
+            Move::Promote(_, t, _) => {
+                match board[t.0 as usize][t.1 as usize].piece.idx {
+                    Some(_) => board[t.0 as usize][t.1 as usize].threatened = true,
+                    None => board[t.0 as usize][t.1 as usize].piece.idx = Some(6)
+                }
+            }
+            Move::Drop(_, _, t) => {
+                board[t.0 as usize][t.1 as usize].piece.idx = Some(6);
             },
-            _ => todo!()
         }
     }
 }
@@ -207,12 +234,114 @@ impl Faction {
 }
 
 
+// One bit per castle still allowed, kingside being the side of the rook closest to the king
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct CastlingRights(pub u8);
+
+impl CastlingRights {
+    pub const NONE: Self = Self(0);
+    pub const ALL: Self = Self(0b1111);
+
+    fn bit(color: &Color, kingside: bool) -> u8 {
+        match (color, kingside) {
+            (Color::White, true) => 0b0001,
+            (Color::White, false) => 0b0010,
+            (Color::Black, true) => 0b0100,
+            (Color::Black, false) => 0b1000
+        }
+    }
+
+    pub fn has(&self, color: &Color, kingside: bool) -> bool {
+        self.0 & Self::bit(color, kingside) != 0
+    }
+
+    pub fn insert(&mut self, color: &Color, kingside: bool) {
+        self.0 |= Self::bit(color, kingside);
+    }
+
+    pub fn remove(&mut self, color: &Color, kingside: bool) {
+        self.0 &= !Self::bit(color, kingside);
+    }
+}
+
+// Pieces taken in Crazyhouse, waiting to be dropped: pawns, knights, bishops, rooks and queens
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub struct Pocket(pub [u8; 5]);
+
+impl Pocket {
+    pub const PIECES: [PieceType; 5] = [PieceType::Pawn, PieceType::Knight, PieceType::Bishop, PieceType::Rook, PieceType::Queen];
+
+    fn index(ptype: &PieceType) -> Option<usize> {
+        Self::PIECES.iter().position(|p| p == ptype)
+    }
+
+    pub fn count(&self, ptype: &PieceType) -> u8 {
+        Self::index(ptype).map_or(0, |i| self.0[i])
+    }
+
+    // Kings are never taken, nothing happens for them
+    pub fn add(&mut self, ptype: &PieceType) {
+        if let Some(i) = Self::index(ptype) {
+            self.0[i] += 1;
+        }
+    }
+
+    // False when there is none to take
+    pub fn take(&mut self, ptype: &PieceType) -> bool {
+        match Self::index(ptype) {
+            Some(i) if self.0[i] > 0 => {
+                self.0[i] -= 1;
+                true
+            },
+            _ => false
+        }
+    }
+
+    pub fn pieces(&self) -> impl Iterator<Item = PieceType> + '_ {
+        Self::PIECES.into_iter().filter(|p| self.count(p) > 0)
+    }
+}
+
+// Everything `ChessBoard::play_once` can change, as it was before the move
+#[derive(Debug, Clone)]
+pub struct BoardUndo {
+    squares: Vec<(Position, Piece)>,
+    castling_rights: CastlingRights,
+    en_passant: Option<Position>,
+    side_to_move: Color,
+    halfmove_clock: usize,
+    fullmove_number: usize,
+    checks: [u8; 2],
+    pockets: Option<[Pocket; 2]>,
+    promoted: Bitboard,
+    hash: u64
+}
+
 #[derive(Debug, Clone)]
 pub struct ChessBoard {
     pub board: Vec<Vec<Piece>>,
     pub faction: Faction,
-    pub headstart: Option<Position>,
-    self_key: Option<String>
+    pub castling_rights: CastlingRights,
+    // Files the castling rooks start from, white's then black's, each as [queenside, kingside]
+    pub castling_files: [[i8; 2]; 2],
+    // Castling is written king takes rook in UCI, and the PGN gets a variant tag
+    pub chess960: bool,
+    // The square a pawn that just made its double step can be taken on
+    pub en_passant: Option<Position>,
+    pub side_to_move: Color,
+    // Plies since the last capture or pawn move, and the FEN full move number
+    pub halfmove_clock: usize,
+    pub fullmove_number: usize,
+    // Checks given by white then black, only Three-check counts them
+    pub checks: [u8; 2],
+    // White's then black's, `None` outside Crazyhouse where taken pieces are simply gone
+    pub pockets: Option<[Pocket; 2]>,
+    // Pieces born from a promotion, they go back to the pocket as pawns
+    pub promoted: Bitboard,
+    // Kept in sync with `board`, move generation and attacks are computed on it
+    pub bitboards: Bitboards,
+    // Zobrist hash of the position, updated along with every square and state change
+    hash: u64
 }
 
 impl ChessBoard {
@@ -221,24 +350,85 @@ impl ChessBoard {
         let mut board = Self {
             board: board,
             faction: Faction::new_empty(),
-            headstart: None,
-            self_key: None
+            castling_rights: CastlingRights::ALL,
+            castling_files: ORTHODOX_CASTLING_FILES,
+            chess960: false,
+            en_passant: None,
+            side_to_move: Color::White,
+            halfmove_clock: 0,
+            fullmove_number: 1,
+            checks: [0, 0],
+            pockets: None,
+            promoted: 0,
+            bitboards: Bitboards::default(),
+            hash: 0
         };
         board.collect_factions();
         board
     }
 
+    // The Fischer Random position of that index in Scharnagl's numbering, 518 being the orthodox one
+    pub fn new_chess960(index: usize) -> Result<Self, ChessError> {
+        if index >= 960 {
+            return Err(ChessError::InvalidChess960Index(index))
+        }
+        let mut pieces: [Option<PieceType>; 8] = Default::default();
+        let (n, light_bishop) = (index / 4, index % 4);
+        pieces[2 * light_bishop + 1] = Some(PieceType::Bishop);
+        let (n, dark_bishop) = (n / 4, n % 4);
+        pieces[2 * dark_bishop] = Some(PieceType::Bishop);
+        let (n, queen) = (n / 6, n % 6);
+        let knights = [(0, 1), (0, 2), (0, 3), (0, 4), (1, 2), (1, 3), (1, 4), (2, 3), (2, 4), (3, 4)][n];
+        // The queen and knights take the n-th free file, the rooks and king fill the last three in that order
+        let mut place = |nth: usize, ptype: PieceType| {
+            let file = (0..8).filter(|f| pieces[*f].is_none()).nth(nth).unwrap();
+            pieces[file] = Some(ptype);
+        };
+        place(queen, PieceType::Queen);
+        place(knights.1, PieceType::Knight);
+        place(knights.0, PieceType::Knight);
+        for ptype in [PieceType::Rook, PieceType::King, PieceType::Rook] {
+            place(0, ptype);
+        }
+
+        let mut board = Self::new_empty();
+        for color in [Color::White, Color::Black] {
+            for (file, ptype) in pieces.iter().enumerate() {
+                board.put_piece((back_rank(&color), file as i8), ptype.clone().unwrap(), color.clone())?;
+                board.put_piece((pawn_start_rank(&color), file as i8), PieceType::Pawn, color.clone())?;
+            }
+            board.set_castling(&color, true, true)?;
+            board.set_castling(&color, false, true)?;
+        }
+        board.chess960 = true;
+        Ok(board)
+    }
+
     pub fn new_empty() -> Self {
-        Self {
+        let mut board = Self {
             board: empty_board(),
             faction: Faction::new_empty(),
-            headstart: None,
-            self_key: None
-        }
+            castling_rights: CastlingRights::NONE,
+            castling_files: ORTHODOX_CASTLING_FILES,
+            chess960: false,
+            en_passant: None,
+            side_to_move: Color::White,
+            halfmove_clock: 0,
+            fullmove_number: 1,
+            checks: [0, 0],
+            pockets: None,
+            promoted: 0,
+            bitboards: Bitboards::default(),
+            hash: 0
+        };
+        board.refresh_hash();
+        board
     }
 
     pub fn collect_factions(&mut self) {
         self.faction.clear();
+        self.bitboards = Bitboards::from_board(&self.board);
+        self.refresh_hash();
         for i in 0..8 {
             for j in 0..8 {
                 match self.board[i][j].color() {
@@ -251,130 +441,270 @@ impl ChessBoard {
         }
     }
 
-    pub fn locate_king(&self, faction: &Color) -> Position {
-        match faction {
-            Color::Black => match self.faction.black_pieces_by_type.get(&PieceType::King).unwrap() {
-                pos => match self.board[pos.0 as usize][pos.1 as usize] {
-                   Piece::King(ref k) => k.position(),
-                   _ => {
-                        self.pprint();
-                        panic!("There should always be king on the board")
-                   }
-                },
-                _ => panic!("There should always be king on the board")
-            },
-            Color::White => match self.faction.white_pieces_by_type.get(&PieceType::King).unwrap() {
-                pos => match self.board[pos.0 as usize][pos.1 as usize] {
-                    Piece::King(ref k) => k.position(),
-                    _ => {
-                        self.pprint();
-                        panic!("There should always be king on the board")
-                    }
-                 },
-                 _ => {
-                    self.pprint();
-                    panic!("There should always be king on the board")
-                 }
+    fn king_entry(&self, faction: &Color) -> Result<Position, ChessError> {
+        let by_type = match faction {
+            Color::Black => &self.faction.black_pieces_by_type,
+            Color::White => &self.faction.white_pieces_by_type
+        };
+        by_type.get(&PieceType::King).cloned().ok_or_else(|| ChessError::NoKing(faction.clone()))
+    }
+
+    pub fn locate_king(&self, faction: &Color) -> Result<Position, ChessError> {
+        let pos = self.king_entry(faction)?;
+        match self.board[pos.0 as usize][pos.1 as usize] {
+            Piece::King(ref k) => Ok(k.position()),
+            _ => Err(ChessError::NoKing(faction.clone()))
+        }
+    }
+
+    pub fn locate_king_mut(&mut self, faction: &Color) -> Result<&mut King, ChessError> {
+        let pos = self.king_entry(faction)?;
+        match self.board[pos.0 as usize][pos.1 as usize] {
+            Piece::King(ref mut k) => Ok(k),
+            _ => Err(ChessError::NoKing(faction.clone()))
+        }
+    }
+
+    // Identifies a position for repetition and transpositions: placement, side to move, castling and en passant
+    pub fn position_hash(&self) -> u64 {
+        self.hash
+    }
+
+    // To call after changing the board without going through `set_square` or `play_once`
+    pub fn refresh_hash(&mut self) {
+        self.hash = Zobrist::keys().hash(self);
+    }
+
+    pub fn add_check(&mut self, color: &Color) {
+        let keys = Zobrist::keys();
+        self.hash ^= keys.state(self);
+        self.checks[color_index(color)] += 1;
+        self.hash ^= keys.state(self);
+    }
+
+    // No sequence of legal moves can lead to a checkmate (K vs K, K + minor vs K, bishops on a single color)
+    pub fn has_insufficient_material(&self) -> bool {
+        let mut minors = vec!();
+        for pos in self.faction.white_pieces.values().chain(self.faction.black_pieces.values()) {
+            match self.board[pos.0 as usize][pos.1 as usize].get_type() {
+                Some(PieceType::King) => {},
+                Some(ptype @ (PieceType::Knight | PieceType::Bishop)) => minors.push((ptype, *pos)),
+                _ => return false
             }
         }
+        match minors.as_slice() {
+            [] | [_] => true,
+            bishops if bishops.iter().all(|(t, _)| *t == PieceType::Bishop) => {
+                let square_color = |p: &Position| (p.0 + p.1) % 2;
+                let first = square_color(&bishops[0].1);
+                bishops.iter().all(|(_, p)| square_color(p) == first)
+            },
+            _ => false
+        }
+    }
+
+    // Legal moves of a faction: pseudo legal moves that don't leave the king attacked, plus castling
+    pub fn gen_all_moves(&self, player: &Color) -> Vec<Move> {
+        let mut moves = self.bitboards.pseudo_legal_moves(player, self.en_passant_target(player));
+        moves.retain(|m| self.bitboards.is_legal(m, player));
+        moves.extend(self.castling_moves(player));
+        moves
     }
 
-    pub fn locate_king_mut(&mut self, faction: &Color) -> &mut King {
+    pub fn update_controlled_squares(&mut self, faction: &Color) {
+        let controlled = bitboard::squares(self.bitboards.controlled(faction))
+            .map(bitboard::position)
+            .collect();
         match faction {
-            Color::Black => match self.faction.black_pieces_by_type.get(&PieceType::King).unwrap() {
-                pos => match self.board[pos.0 as usize][pos.1 as usize] {
-                   Piece::King(ref mut k) => k,
-                   _ => panic!("There should always be king on the board")
-                },
-                _ => panic!("There should always be king on the board")
-            },
-            Color::White => match self.faction.white_pieces_by_type.get(&PieceType::King).unwrap() {
-                pos => match self.board[pos.0 as usize][pos.1 as usize] {
-                    Piece::King(ref mut k) => k,
-                    _ => panic!("There should always be king on the board")
-                 },
-                 _ => panic!("There should always be king on the board")
-            }
+            Color::Black => self.faction.black_controlled = controlled,
+            Color::White => self.faction.white_controlled = controlled
         }
     }
 
-    pub fn get_board_as_key(&mut self) -> String {
-        match self.self_key {
-            Some(ref key) => key.clone(),
-            None => {
-                let mut repr = String::new();
-                repr.reserve(64);
-                for i in 0..8 {
-                    for j in 0..8 {
-                        repr.push_str(self.board[i][j].emoji());
-                    }
-                }
-                self.self_key = Some(repr.clone());
-                repr
-            }
+    // Every write to a square goes through here to keep the bitboards in sync
+    pub fn set_square(&mut self, pos: Position, piece: Piece) {
+        let (i, j) = (pos.0 as usize, pos.1 as usize);
+        let keys = Zobrist::keys();
+        self.hash ^= keys.piece(&pos, &self.board[i][j]) ^ keys.piece(&pos, &piece);
+        self.bitboards.remove(&pos, &self.board[i][j]);
+        self.bitboards.put(&pos, &piece);
+        self.board[i][j] = piece;
+    }
+
+    // Ids are unique among both factions, new pieces take the next free one
+    fn next_piece_id(&self) -> usize {
+        self.faction.white_pieces.keys()
+            .chain(self.faction.black_pieces.keys())
+            .max()
+            .map_or(1, |id| id + 1)
+    }
+
+    fn check_square(pos: Position) -> Result<(), ChessError> {
+        if !(0..8).contains(&pos.0) || !(0..8).contains(&pos.1) {
+            return Err(ChessError::OutOfBoard(pos))
         }
+        Ok(())
     }
 
-    // !! The bord mutations have to be handled outside this function
-    pub fn gen_all_moves(&self, player: &Color, check: bool, attack_vectors: &Vec<HashSet<Position>>) -> Vec<Move> {
-        match player {
-            Color::Black => {
-                self.faction.black_pieces
-                    .values()
-                    .flat_map(|p| {
-                        self.board[p.0 as usize][p.1 as usize].gen_moves_check(self, check, attack_vectors)
-                    })
-                    .filter(|m| m.is_valid(self))
-                    .collect()
-            },
-            Color::White => {
-                self.faction.white_pieces
-                    .values()
-                    .flat_map(|p| {
-                        self.board[p.0 as usize][p.1 as usize].gen_moves_check(self, check, attack_vectors)
-                    })
-                    .filter(|m| m.is_valid(self))
-                    .collect()
+    // Board editor, the pieces placed are considered unmoved only if castling rights say so
+    pub fn put_piece(&mut self, pos: Position, ptype: PieceType, color: Color) -> Result<(), ChessError> {
+        self.remove_piece(pos)?;
+        if ptype == PieceType::Empty {
+            return Ok(())
+        }
+        let has_moved = ptype != PieceType::Pawn || pos.0 != pawn_start_rank(&color);
+        let mut piece = Piece::new(pos, color, ptype, self.next_piece_id());
+        piece.set_has_moved(has_moved);
+        self.faction.upsert(piece.clone());
+        self.set_square(pos, piece);
+        self.after_edit();
+        Ok(())
+    }
+
+    // Returns the piece that was on the square
+    pub fn remove_piece(&mut self, pos: Position) -> Result<Piece, ChessError> {
+        Self::check_square(pos)?;
+        let piece = self.board[pos.0 as usize][pos.1 as usize].clone();
+        if piece.is_empty() {
+            return Ok(piece)
+        }
+        self.empty_square(pos);
+        self.after_edit();
+        Ok(piece)
+    }
+
+    // Takes the piece off its square and out of its faction, the state part of the hash is left to the caller
+    fn empty_square(&mut self, pos: Position) -> Piece {
+        let piece = self.board[pos.0 as usize][pos.1 as usize].clone();
+        self.faction.delete(&piece);
+        self.set_square(pos, Piece::Empty);
+        self.promoted &= !bitboard::bit(&pos);
+        // The faction keeps one entry per type, another piece of the removed type has to get it back
+        if let (Some(color), Some(ptype)) = (piece.color(), piece.get_type()) {
+            if let Some(sq) = bitboard::squares(self.bitboards.pieces(&color, &ptype)).next() {
+                let other = bitboard::position(sq);
+                self.faction.upsert(self.board[other.0 as usize][other.1 as usize].clone());
             }
         }
+        piece
     }
 
-    pub fn reset_pin_vectors(&mut self, faction: &Color) {
-        match faction {
-            Color::Black => {
-                for pos in self.faction.black_pieces.values() {
-                    self.board[pos.0 as usize][pos.1 as usize].set_attack_vector(None);
-                }
-            },
-            Color::White => {
-                for pos in self.faction.white_pieces.values() { 
-                    self.board[pos.0 as usize][pos.1 as usize].set_attack_vector(None);
-                }
+    // Empties several squares at once (an Atomic explosion) for the cost of a single move, unlike `remove_piece`
+    // Nothing is changed if one of the squares is out of the board
+    pub fn clear_squares(&mut self, positions: impl IntoIterator<Item = Position>) -> Result<(), ChessError> {
+        let positions: Vec<Position> = positions.into_iter().collect();
+        for pos in &positions {
+            Self::check_square(*pos)?;
+        }
+        let keys = Zobrist::keys();
+        self.hash ^= keys.state(self);
+        for pos in positions {
+            self.empty_square(pos);
+        }
+        self.drop_lost_castles();
+        self.hash ^= keys.state(self);
+        Ok(())
+    }
+
+    pub fn clear(&mut self) {
+        self.board = empty_board();
+        self.castling_rights = CastlingRights::NONE;
+        self.en_passant = None;
+        self.collect_factions();
+    }
+
+    pub fn set_side_to_move(&mut self, color: Color) {
+        self.side_to_move = color;
+        self.en_passant = None;
+        self.refresh_hash();
+    }
+
+    // Castling with the outermost rook of that side of the king
+    pub fn set_castling(&mut self, color: &Color, kingside: bool, allowed: bool) -> Result<(), ChessError> {
+        if !allowed {
+            self.castling_rights.remove(color, kingside);
+            self.refresh_hash();
+            return Ok(())
+        }
+        let file = self.outermost_rook(color, kingside)
+            .ok_or_else(|| ChessError::CastlingUnavailable(color.clone(), kingside))?;
+        self.set_castling_rook(color, kingside, file)
+    }
+
+    // Castling with the rook on the given file, which has to be on the back rank on that side of the king
+    pub fn set_castling_rook(&mut self, color: &Color, kingside: bool, file: i8) -> Result<(), ChessError> {
+        let previous = self.castling_files[color_index(color)][kingside as usize];
+        self.castling_files[color_index(color)][kingside as usize] = file;
+        let (king_pos, rook_pos) = match self.castling_pieces(color, kingside) {
+            Some(pieces) => pieces,
+            None => {
+                self.castling_files[color_index(color)][kingside as usize] = previous;
+                return Err(ChessError::CastlingUnavailable(color.clone(), kingside))
             }
+        };
+        self.board[king_pos.0 as usize][king_pos.1 as usize].set_has_moved(false);
+        self.board[rook_pos.0 as usize][rook_pos.1 as usize].set_has_moved(false);
+        self.castling_rights.insert(color, kingside);
+        self.refresh_hash();
+        Ok(())
+    }
+
+    pub fn castling_file(&self, color: &Color, kingside: bool) -> i8 {
+        self.castling_files[color_index(color)][kingside as usize]
+    }
+
+    // The rook furthest from the king on its side of the back rank
+    pub fn outermost_rook(&self, color: &Color, kingside: bool) -> Option<i8> {
+        let king_pos = bitboard::position(self.bitboards.king_square(color)?);
+        let row = back_rank(color);
+        let mut files: Vec<i8> = if kingside { (king_pos.1 + 1..8).rev().collect() } else { (0..king_pos.1).collect() };
+        files.retain(|file| {
+            let piece = &self.board[row as usize][*file as usize];
+            piece.get_type() == Some(PieceType::Rook) && piece.color().as_ref() == Some(color)
+        });
+        files.first().cloned().filter(|_| king_pos.0 == row)
+    }
+
+    // The king on its back rank, and the castling rook on the same rank on the right side of it
+    pub(crate) fn castling_pieces(&self, color: &Color, kingside: bool) -> Option<(Position, Position)> {
+        let (king_pos, _, rook_pos, _) = self.castling_squares(color, kingside).ok()?;
+        if king_pos.0 != back_rank(color) || (rook_pos.1 > king_pos.1) != kingside {
+            return None
+        }
+        let rook = &self.board[rook_pos.0 as usize][rook_pos.1 as usize];
+        if rook.get_type() != Some(PieceType::Rook) || rook.color().as_ref() != Some(color) {
+            return None
         }
+        Some((king_pos, rook_pos))
     }
 
-    pub fn update_controlled_squares(&mut self, faction: &Color) {
-        match faction {
-            Color::Black => {
-                let mut black_controlled = HashSet::new();
-                for pos in self.faction.black_pieces.values() {
-                    let piece = &self.board[pos.0 as usize][pos.1 as usize];
-                    black_controlled.extend(piece.get_controlled_squares(self));
-                }
-                self.faction.black_controlled = black_controlled;
-            },
-            Color::White => {
-                let mut white_controlled = HashSet::new();
-                for pos in self.faction.white_pieces.values() {
-                    let piece = &self.board[pos.0 as usize][pos.1 as usize];
-                    white_controlled.extend(piece.get_controlled_squares(self));
+    // An edited position has no en passant, and keeps only the castles its pieces still allow
+    fn after_edit(&mut self) {
+        self.en_passant = None;
+        self.drop_lost_castles();
+        self.refresh_hash();
+    }
+
+    // The castles whose king or rook is gone, the hash is left to the caller
+    fn drop_lost_castles(&mut self) {
+        for color in [Color::White, Color::Black] {
+            for kingside in [true, false] {
+                if self.castling_pieces(&color, kingside).is_none() {
+                    self.castling_rights.remove(&color, kingside);
                 }
-                self.faction.white_controlled = white_controlled;
             }
         }
     }
 
+    // Returns (king_from, king_to, rook_from, rook_to): wherever they start, the king lands on the g-file (kingside)
+    // or the c-file (queenside) and the rook next to it, as in Chess960
+    pub fn castling_squares(&self, color: &Color, kingside: bool) -> Result<(Position, Position, Position, Position), ChessError> {
+        let king_pos = self.locate_king(color)?;
+        let row = king_pos.0;
+        let (king_col, rook_col) = if kingside { (6, 5) } else { (2, 3) };
+        Ok((king_pos, (row, king_col), (row, self.castling_file(color, kingside)), (row, rook_col)))
+    }
+
     fn castle(&mut self, king_from: Position, king_to: Position, rook_from: Position, rook_to: Position){
         let mut king = self.board[king_from.0 as usize][king_from.1 as usize].clone();
         let mut rook = self.board[rook_from.0 as usize][rook_from.1 as usize].clone();
@@ -382,17 +712,159 @@ impl ChessBoard {
         rook.set_position(rook_to);
         self.faction.upsert(king.clone());
         self.faction.upsert(rook.clone());
-        self.board[king_from.0 as usize][king_from.1 as usize] = Piece::Empty;
-        self.board[rook_from.0 as usize][rook_from.1 as usize] = Piece::Empty;
-        self.board[king_to.0 as usize][king_to.1 as usize] = king;
-        self.board[rook_to.0 as usize][rook_to.1 as usize] = rook;
+        self.set_square(king_from, Piece::Empty);
+        self.set_square(rook_from, Piece::Empty);
+        self.set_square(king_to, king);
+        self.set_square(rook_to, rook);
+    }
+
+    // The squares whose content is changed by the move
+    fn touched_squares(&self, m: &Move) -> Result<Vec<Position>, ChessError> {
+        let squares = match m {
+            Move::Move(from, to) | Move::Take(from, to) | Move::Promote(from, to, _) => vec![*from, *to],
+            Move::EnPassant(from, to) => vec![*from, *to, (from.0, to.1)],
+            Move::KingsideCastle(c) | Move::QueensideCastle(c) => {
+                let kingside = matches!(m, Move::KingsideCastle(_));
+                let (king_from, king_to, rook_from, rook_to) = self.castling_squares(c, kingside)?;
+                vec![king_from, king_to, rook_from, rook_to]
+            },
+            Move::Drop(_, _, to) => vec![*to],
+        };
+        Ok(squares)
+    }
+
+    // Plays the move and returns what is needed to take it back with `unmake_move`
+    pub fn make_move(&mut self, m: Move) -> Result<BoardUndo, ChessError> {
+        self.make_move_saving(m, &[])
+    }
+
+    // Same as `make_move`, also saving squares that something else than the move will change
+    pub fn make_move_saving(&mut self, m: Move, extra: &[Position]) -> Result<BoardUndo, ChessError> {
+        let squares = self.touched_squares(&m)?
+            .into_iter()
+            .chain(extra.iter().copied())
+            .map(|pos| (pos, self.board[pos.0 as usize][pos.1 as usize].clone()))
+            .collect();
+        let undo = BoardUndo {
+            squares: squares,
+            castling_rights: self.castling_rights,
+            en_passant: self.en_passant,
+            side_to_move: self.side_to_move.clone(),
+            halfmove_clock: self.halfmove_clock,
+            fullmove_number: self.fullmove_number,
+            checks: self.checks,
+            pockets: self.pockets,
+            promoted: self.promoted,
+            hash: self.hash
+        };
+        self.play_once(m)?;
+        Ok(undo)
+    }
+
+    pub fn unmake_move(&mut self, undo: BoardUndo) {
+        for (pos, _) in &undo.squares {
+            let current = &self.board[pos.0 as usize][pos.1 as usize];
+            self.faction.delete(current);
+        }
+        for (pos, piece) in undo.squares {
+            self.faction.upsert(piece.clone());
+            self.set_square(pos, piece);
+        }
+        self.castling_rights = undo.castling_rights;
+        self.en_passant = undo.en_passant;
+        self.side_to_move = undo.side_to_move;
+        self.halfmove_clock = undo.halfmove_clock;
+        self.fullmove_number = undo.fullmove_number;
+        self.checks = undo.checks;
+        self.pockets = undo.pockets;
+        self.promoted = undo.promoted;
+        self.hash = undo.hash;
+    }
+
+    // The moving piece has to exist, nothing is changed otherwise
+    fn check_move(&self, m: &Move) -> Result<(), ChessError> {
+        let (from, to) = match m {
+            Move::Move(from, to) | Move::Take(from, to) | Move::EnPassant(from, to) | Move::Promote(from, to, _) => (*from, *to),
+            Move::KingsideCastle(c) | Move::QueensideCastle(c) => return self.locate_king(c).map(|_| ()),
+            Move::Drop(c, ptype, to) => return self.check_drop(c, ptype, *to),
+        };
+        for pos in [from, to] {
+            if !(0..8).contains(&pos.0) || !(0..8).contains(&pos.1) {
+                return Err(ChessError::OutOfBoard(pos))
+            }
+        }
+        match self.board[from.0 as usize][from.1 as usize] {
+            Piece::Empty => Err(ChessError::EmptySquare(from)),
+            _ => Ok(())
+        }
     }
 
-    // returns the promotion position if the move leads to a promotion
-    pub fn play_once(&mut self, m: Move) -> Option<Position> {
-        self.self_key = None;
-        let mut headstart = None;
-        let mut promotion = None;
+    // The piece comes from a pocket and lands on an empty square
+    fn check_drop(&self, color: &Color, ptype: &PieceType, to: Position) -> Result<(), ChessError> {
+        Self::check_square(to)?;
+        let in_pocket = self.pockets.is_some_and(|pockets| pockets[color_index(color)].count(ptype) > 0);
+        if !in_pocket {
+            return Err(ChessError::EmptyPocket(color.clone(), ptype.clone()))
+        }
+        match self.board[to.0 as usize][to.1 as usize] {
+            Piece::Empty => Ok(()),
+            _ => Err(ChessError::OccupiedSquare(to))
+        }
+    }
+
+    // Where the piece the move takes stands
+    fn captured_square(&self, m: &Move) -> Option<Position> {
+        let square = match m {
+            Move::Take(_, to) | Move::Promote(_, to, _) => *to,
+            Move::EnPassant(from, to) => (from.0, to.1),
+            _ => return None
+        };
+        (!self.board[square.0 as usize][square.1 as usize].is_empty()).then_some(square)
+    }
+
+    pub fn captured(&self, m: &Move) -> Option<Piece> {
+        self.captured_square(m).map(|pos| self.board[pos.0 as usize][pos.1 as usize].clone())
+    }
+
+    // In Crazyhouse, the taken piece changes sides and goes to the pocket, a promoted one as a pawn
+    fn pocket_capture(&mut self, m: &Move) {
+        let Some(square) = self.captured_square(m) else {
+            return
+        };
+        let piece = &self.board[square.0 as usize][square.1 as usize];
+        let ptype = match self.promoted & bitboard::bit(&square) {
+            0 => piece.get_type().unwrap(),
+            _ => PieceType::Pawn
+        };
+        let capturer = piece.color().unwrap().other();
+        if let Some(pockets) = self.pockets.as_mut() {
+            pockets[color_index(&capturer)].add(&ptype);
+        }
+        self.promoted &= !bitboard::bit(&square);
+    }
+
+    // Promoted pieces carry their mark along when they move
+    fn update_promoted(&mut self, m: &Move) {
+        match m {
+            Move::Move(from, to) | Move::Take(from, to) if self.promoted & bitboard::bit(from) != 0 => {
+                self.promoted ^= bitboard::bit(from) | bitboard::bit(to);
+            },
+            Move::Promote(_, to, _) => self.promoted |= bitboard::bit(to),
+            _ => {}
+        }
+    }
+
+    pub fn play_once(&mut self, m: Move) -> Result<(), ChessError> {
+        self.check_move(&m)?;
+        // The state part of the hash is xored out now and back in once the move is played
+        self.hash ^= Zobrist::keys().state(self);
+        self.pocket_capture(&m);
+        self.update_promoted(&m);
+        let mut en_passant = None;
+        self.update_move_counters(&m);
+        if let Ok(squares) = self.touched_squares(&m) {
+            self.update_castling_rights(&squares);
+        }
         match m {
             Move::Take(from, to) => {
                 let mut p = self.board[from.0 as usize][from.1 as usize].clone();
@@ -400,37 +872,19 @@ impl ChessBoard {
                 p.set_position(to.clone());
                 self.faction.upsert(p.clone());
                 self.faction.delete(piece_to_delete);
-                self.board[from.0 as usize][from.1 as usize] = Piece::Empty;
-                let piece_color = p.color().unwrap();
-                let piece_type = p.get_type().unwrap();
-                self.board[to.0 as usize][to.1 as usize] = p;
-                match (piece_type, piece_color, to) {
-                    (PieceType::Pawn, Color::Black, to @ (7, _)) => promotion = Some(to),
-                    (PieceType::Pawn, Color::White, to @ (0, _)) => promotion = Some(to),
-                    _ => {}
-                }
+                self.set_square(from, Piece::Empty);
+                self.set_square(to, p);
             },
             Move::Move(from, to) => {
                 let mut p = self.board[from.0 as usize][from.1 as usize].clone();
-                // If we have a pawn headstart
-                match p {
-                    Piece::Pawn(ref mut p)if !p.has_moved && (p.position.0 - to.0).abs() == 2 => {
-                        p.headstart = true;
-                        headstart = Some(to)
-                    },
-                    _ => {}
+                // A double step can be taken en passant on the square it skipped
+                if p.get_type() == Some(PieceType::Pawn) && (from.0 - to.0).abs() == 2 {
+                    en_passant = Some(((from.0 + to.0) / 2, to.1));
                 }
                 p.set_position(to.clone());
                 self.faction.upsert(p.clone());
-                self.board[from.0 as usize][from.1 as usize] = Piece::Empty;
-                let piece_color = p.color().unwrap();
-                let piece_type = p.get_type().unwrap();
-                self.board[to.0 as usize][to.1 as usize] = p;
-                match (piece_type, piece_color, to) {
-                    (PieceType::Pawn, Color::Black, to @ (7, _)) => promotion = Some(to),
-                    (PieceType::Pawn, Color::White, to @ (0, _)) => promotion = Some(to),
-                    _ => {}
-                }
+                self.set_square(from, Piece::Empty);
+                self.set_square(to, p);
             },
             Move::EnPassant(from, to) => {
                 let mut p = self.board[from.0 as usize][from.1 as usize].clone();
@@ -438,48 +892,82 @@ impl ChessBoard {
                 p.set_position(to.clone());
                 self.faction.upsert(p.clone());
                 self.faction.delete(piece_to_delete);
-                self.board[from.0 as usize][from.1 as usize] = Piece::Empty;
-                self.board[from.0 as usize][to.1 as usize] = Piece::Empty;
-                let piece_color = p.color().unwrap();
-                self.board[to.0 as usize][to.1 as usize] = p;
+                self.set_square(from, Piece::Empty);
+                self.set_square((from.0, to.1), Piece::Empty);
+                self.set_square(to, p);
             },
-            Move::KingsideCastle(faction) => {
-                match faction {
-                    Color::White => {
-                        self.castle((7, 4), (7, 6), (7, 7), (7, 5))
-                    }
-                    Color::Black => {
-                        self.castle((0, 3), (0, 1), (0, 0), (0, 2))
-                    }
-                }
+            Move::KingsideCastle(ref faction) | Move::QueensideCastle(ref faction) => {
+                let kingside = matches!(m, Move::KingsideCastle(_));
+                let (king_from, king_to, rook_from, rook_to) = self.castling_squares(faction, kingside)?;
+                self.castle(king_from, king_to, rook_from, rook_to)
             },
-            Move::QueensideCastle(faction) => {
-                match faction {
-                    Color::White => {
-                        self.castle((7, 4), (7, 2), (7, 0), (7, 3))
-                    }
-                    Color::Black => {
-                        self.castle((0, 3), (0, 5), (0, 7), (0, 4))
-                    }
+            Move::Promote(from, to, to_type) => {
+                let pawn = &self.board[from.0 as usize][from.1 as usize];
+                let piece_color = pawn.color().ok_or(ChessError::EmptySquare(from))?;
+                let piece_id = pawn.get_id().ok_or(ChessError::EmptySquare(from))?;
+                // The new piece keeps the pawn id, so it replaces it in its faction
+                let new_piece = Piece::new(to, piece_color, to_type.into(), piece_id);
+                self.faction.delete(&self.board[to.0 as usize][to.1 as usize]);
+                self.faction.upsert(new_piece.clone());
+                self.set_square(from, Piece::Empty);
+                self.set_square(to, new_piece);
+            },
+            Move::Drop(color, ptype, to) => {
+                if let Some(pockets) = self.pockets.as_mut() {
+                    pockets[color_index(&color)].take(&ptype);
                 }
+                // The piece gets a new id, as any piece entering the board
+                let mut piece = Piece::new(to, color, ptype, self.next_piece_id());
+                piece.set_has_moved(true);
+                self.faction.upsert(piece.clone());
+                self.set_square(to, piece);
             },
-            Move::Promote(from, to_type) => {
-                let piece = &self.board[from.0 as usize][from.1 as usize];
-                let piece_color = piece.color().unwrap();
-                let piece_id = piece.get_id().unwrap();
-                let new_piece = Piece::new(from, piece_color.clone(), to_type.into(), piece_id);
-                self.faction.upsert(new_piece.clone());
-                self.board[from.0 as usize][from.1 as usize] = new_piece;
-            }
-            _ => {}
             // We update the controled squares for each faction
         }
-        self.update_headstart(headstart);
-        promotion
+        self.en_passant = en_passant;
+        self.hash ^= Zobrist::keys().state(self);
+        debug_assert_eq!(self.hash, Zobrist::keys().hash(self));
+        Ok(())
+    }
+
+    // A king leaving its square loses both castles, a rook leaving or taken on its corner loses its own
+    fn update_castling_rights(&mut self, touched: &[Position]) {
+        for color in [Color::White, Color::Black] {
+            if !self.castling_rights.has(&color, true) && !self.castling_rights.has(&color, false) {
+                continue
+            }
+            for kingside in [true, false] {
+                let (king_pos, _, rook_from, _) = match self.castling_squares(&color, kingside) {
+                    Ok(squares) => squares,
+                    Err(_) => continue
+                };
+                if touched.contains(&king_pos) || touched.contains(&rook_from) {
+                    self.castling_rights.remove(&color, kingside);
+                }
+            }
+        }
+    }
+
+    fn update_move_counters(&mut self, m: &Move) {
+        let resets_clock = match m {
+            Move::Take(_, _) | Move::EnPassant(_, _) | Move::Promote(_, _, _) => true,
+            Move::Move(from, _) => self.board[from.0 as usize][from.1 as usize].get_type() == Some(PieceType::Pawn),
+            Move::KingsideCastle(_) | Move::QueensideCastle(_) | Move::Drop(_, _, _) => false,
+        };
+        if resets_clock {
+            self.halfmove_clock = 0;
+        }
+        else {
+            self.halfmove_clock += 1;
+        }
+        if self.side_to_move == Color::Black {
+            self.fullmove_number += 1;
+        }
+        self.side_to_move = self.side_to_move.other();
     }
 
     pub fn pprint(&self) {
-        let sep_row = format!("|{}|", vec!("_"; 8).join("|"));
+        let sep_row = format!("|{}|", ["_"; 8].join("|"));
         for i in 0..8 {
             let mut row = vec!();
             for j in 0..8 {
@@ -492,19 +980,6 @@ impl ChessBoard {
         }
     }
 
-    // 1. white headstart -> update(new_p) // Black headstart -> update(new_p) // black otherwise -> update(None)
-    pub fn update_headstart(&mut self, new_p: Option<Position>) {
-        if let Some(p) = self.headstart {
-            match self.board[p.0 as usize][p.1 as usize] {
-                Piece::Pawn(ref mut p) => {
-                    p.headstart = false;
-                },
-                _ => {}
-            }
-        }
-        self.headstart = new_p;
-    }
-
     pub fn to_webapp(&self) -> WebappRepr {
         let mut repr = vec![vec![CellRepr::default(); 8]; 8];
         for i in 0..8 {
@@ -524,5 +999,128 @@ impl ChessBoard {
         }
         repr
     }
+
+    // The board as seen by a player who only sees the `visible` squares, without control as the enemy one would give hidden pieces away
+    pub fn to_webapp_masked(&self, visible: Bitboard) -> WebappRepr {
+        let mut repr = self.to_webapp();
+        for (sq, cell) in repr.iter_mut().flatten().enumerate() {
+            cell.controll = None;
+            if visible & (1 << sq) == 0 {
+                *cell = CellRepr { hidden: true, ..CellRepr::default() };
+            }
+        }
+        repr
+    }
+}
+
+
+
+#[test]
+fn test_board_editor() {
+    let mut board = ChessBoard::new_empty();
+    board.put_piece((7, 4), PieceType::King, Color::White).unwrap();
+    board.put_piece((7, 7), PieceType::Rook, Color::White).unwrap();
+    board.put_piece((7, 0), PieceType::Rook, Color::White).unwrap();
+    board.put_piece((0, 4), PieceType::King, Color::Black).unwrap();
+    board.put_piece((1, 3), PieceType::Pawn, Color::Black).unwrap();
+    board.put_piece((3, 4), PieceType::Pawn, Color::White).unwrap();
+    board.set_castling(&Color::White, true, true).unwrap();
+    board.set_castling(&Color::White, false, true).unwrap();
+    assert_eq!(board.set_castling(&Color::Black, true, true), Err(ChessError::CastlingUnavailable(Color::Black, true)));
+    board.set_side_to_move(Color::Black);
+    let fen = "4k3/3p4/8/4P3/8/8/8/R3K2R b KQ - 0 1";
+    assert_eq!(board.to_fen(), fen);
+    assert_eq!(board.position_hash(), ChessBoard::from_fen(fen).unwrap().position_hash());
+    // Every piece has its own id
+    let mut ids: Vec<usize> = board.faction.white_pieces.keys().chain(board.faction.black_pieces.keys()).cloned().collect();
+    ids.sort();
+    ids.dedup();
+    assert_eq!(ids.len(), 6);
+    // The edited board plays like any other, the double step still being available
+    board.make_move(Move::Move((1, 3), (3, 3))).unwrap();
+    assert!(board.gen_all_moves(&Color::White).contains(&Move::EnPassant((3, 4), (2, 3))));
+    // Replacing or removing a rook takes its castle away
+    board.put_piece((7, 7), PieceType::Knight, Color::White).unwrap();
+    // The faction's entry for rooks moves on to the one left
+    assert_eq!(board.faction.white_pieces_by_type.get(&PieceType::Rook), Some(&(7, 0)));
+    board.remove_piece((7, 0)).unwrap();
+    assert_eq!(board.faction.white_pieces_by_type.get(&PieceType::Rook), None);
+    assert_eq!(board.faction.white_pieces.len(), 3);
+    assert_eq!(board.castling_rights, CastlingRights::NONE);
+    assert_eq!(board.en_passant, None);
+    assert_eq!(board.remove_piece((8, 0)).err(), Some(ChessError::OutOfBoard((8, 0))));
+    board.clear();
+    assert_eq!(board.to_fen(), "8/8/8/8/8/8/8/8 w - - 0 2");
+    assert!(board.faction.white_pieces.is_empty());
 }
 
+
+#[test]
+fn test_chess960() {
+    assert_eq!(ChessBoard::new_chess960(518).unwrap().to_fen(), crate::fen::STARTING_FEN);
+    assert_eq!(ChessBoard::new_chess960(0).unwrap().to_fen(), "bbqnnrkr/pppppppp/8/8/8/8/PPPPPPPP/BBQNNRKR w KQkq - 0 1");
+    assert_eq!(ChessBoard::new_chess960(959).unwrap().to_fen(), "rkrnnqbb/pppppppp/8/8/8/8/PPPPPPPP/RKRNNQBB w KQkq - 0 1");
+    assert_eq!(ChessBoard::new_chess960(960).err(), Some(ChessError::InvalidChess960Index(960)));
+
+    // The king may already stand on its destination, the rook on the king's
+    let mut board = ChessBoard::from_fen("1r4kr/8/8/8/8/8/8/1R4KR w HBhb - 0 1").unwrap();
+    assert!(board.chess960);
+    let before = board.to_fen();
+    let moves = board.gen_all_moves(&Color::White);
+    assert!(moves.contains(&Move::KingsideCastle(Color::White)) && moves.contains(&Move::QueensideCastle(Color::White)));
+    assert_eq!(Move::KingsideCastle(Color::White).to_uci(&board), "g1h1");
+    let undo = board.make_move(Move::KingsideCastle(Color::White)).unwrap();
+    assert_eq!(board.to_fen(), "1r4kr/8/8/8/8/8/8/1R3RK1 b kq - 1 1");
+    board.unmake_move(undo);
+    assert_eq!(board.to_fen(), before);
+    board.make_move(Move::QueensideCastle(Color::White)).unwrap();
+    assert_eq!(board.to_fen(), "1r4kr/8/8/8/8/8/8/2KR3R b kq - 1 1");
+    // Once the rook has left b1, the king on c1 would be in check from a1
+    let board = ChessBoard::from_fen("8/8/8/8/8/8/8/rRK4k w B - 0 1").unwrap();
+    assert!(!board.gen_all_moves(&Color::White).contains(&Move::QueensideCastle(Color::White)));
+
+    let engine = crate::game::GameEngine::from_board(ChessBoard::new_chess960(0).unwrap());
+    let pgn = crate::pgn::write_pgn(&engine, "White", "Black").unwrap();
+    assert!(pgn.contains("[Variant \"Chess960\"]") && pgn.contains("[FEN \"bbqnnrkr/pppppppp/8/8/8/8/PPPPPPPP/BBQNNRKR w KQkq - 0 1\"]"));
+}
+
+#[test]
+fn test_lookup_errors() {
+    let mut board = ChessBoard::from_fen("4k3/8/8/8/8/8/8/8 w - - 0 1").unwrap();
+    assert_eq!(board.locate_king(&Color::White), Err(ChessError::NoKing(Color::White)));
+    assert!(matches!(board.locate_king_mut(&Color::White), Err(ChessError::NoKing(Color::White))));
+    assert_eq!(board.locate_king(&Color::Black), Ok((0, 4)));
+
+    assert!(matches!(King::self_by_pos(&(8, 4), &mut board), Err(ChessError::OutOfBoard((8, 4)))));
+    assert!(matches!(King::self_by_pos(&(-1, 0), &mut board), Err(ChessError::OutOfBoard((-1, 0)))));
+    assert!(matches!(King::self_by_pos(&(7, 4), &mut board), Err(ChessError::NotAKing((7, 4)))));
+    assert!(King::self_by_pos(&(0, 4), &mut board).is_ok());
+
+    assert_eq!(PieceType::Empty.get_zobrist_id(), None);
+    assert_eq!(Piece::Empty.get_zobrist_id(&(7, 4)), Err(ChessError::EmptySquare((7, 4))));
+    assert_eq!(board.board[0][4].get_zobrist_id(&(0, 4)), Ok(10));
+}
+
+#[test]
+fn test_standard_start_and_castling() {
+    let board = ChessBoard::new_default();
+    assert_eq!(board.to_fen(), crate::fen::STARTING_FEN);
+    assert!(matches!(board.board[7][4], Piece::King(_)) && matches!(board.board[0][4], Piece::King(_)));
+
+    // Both kings castle from the e-file onto g1/c1 and g8/c8, the rook jumping next to them
+    let cases = [
+        (Color::White, true, "e1g1", "r3k2r/8/8/8/8/8/8/R4RK1 b kq - 1 1"),
+        (Color::White, false, "e1c1", "r3k2r/8/8/8/8/8/8/2KR3R b kq - 1 1"),
+        (Color::Black, true, "e8g8", "r4rk1/8/8/8/8/8/8/R3K2R w KQ - 1 2"),
+        (Color::Black, false, "e8c8", "2kr3r/8/8/8/8/8/8/R3K2R w KQ - 1 2"),
+    ];
+    for (color, kingside, uci, fen) in cases {
+        let side = if color == Color::White { "w" } else { "b" };
+        let mut board = ChessBoard::from_fen(&format!("r3k2r/8/8/8/8/8/8/R3K2R {} KQkq - 0 1", side)).unwrap();
+        let castle = if kingside { Move::KingsideCastle(color.clone()) } else { Move::QueensideCastle(color.clone()) };
+        assert!(board.gen_all_moves(&color).contains(&castle));
+        assert_eq!(castle.to_uci(&board), uci);
+        board.make_move(castle).unwrap();
+        assert_eq!(board.to_fen(), fen);
+    }
+}
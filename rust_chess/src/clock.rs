@@ -0,0 +1,72 @@
+use std::time::{Duration, Instant};
+
+use crate::{bitboard::color_index, piece::Color};
+
+// The time left to each side of a board, only the side to move's running
+#[derive(Debug, Clone)]
+pub struct Clock {
+    remaining: [Duration; 2],
+    // The side whose time is running and since when, None until started or once stopped
+    running: Option<(Color, Instant)>
+}
+
+impl Clock {
+    pub fn new(time: Duration) -> Self {
+        Self {
+            remaining: [time; 2],
+            running: None
+        }
+    }
+
+    pub fn start(&mut self, color: Color, now: Instant) {
+        self.running = Some((color, now));
+    }
+
+    pub fn stop(&mut self, now: Instant) {
+        if let Some((color, _)) = &self.running {
+            self.remaining[color_index(color)] = self.remaining(color, now);
+        }
+        self.running = None;
+    }
+
+    // The side that moved stops its time and starts the other one's
+    pub fn press(&mut self, now: Instant) {
+        if let Some((color, _)) = self.running.clone() {
+            self.stop(now);
+            self.start(color.other(), now);
+        }
+    }
+
+    pub fn remaining(&self, color: &Color, now: Instant) -> Duration {
+        let left = self.remaining[color_index(color)];
+        match &self.running {
+            Some((running, since)) if running == color => left.saturating_sub(now.saturating_duration_since(*since)),
+            _ => left
+        }
+    }
+
+    // The side whose time ran out, if any
+    pub fn flagged(&self, now: Instant) -> Option<Color> {
+        [Color::White, Color::Black]
+            .into_iter()
+            .find(|c| self.remaining(c, now).is_zero())
+    }
+}
+
+
+#[test]
+fn test_clock() {
+    let start = Instant::now();
+    let mut clock = Clock::new(Duration::from_secs(60));
+    // Nothing runs before the start
+    assert_eq!(clock.remaining(&Color::White, start + Duration::from_secs(5)), Duration::from_secs(60));
+    clock.start(Color::White, start);
+    assert_eq!(clock.remaining(&Color::White, start + Duration::from_secs(5)), Duration::from_secs(55));
+    clock.press(start + Duration::from_secs(5));
+    assert_eq!(clock.remaining(&Color::White, start + Duration::from_secs(30)), Duration::from_secs(55));
+    assert_eq!(clock.remaining(&Color::Black, start + Duration::from_secs(30)), Duration::from_secs(35));
+    assert_eq!(clock.flagged(start + Duration::from_secs(64)), None);
+    assert_eq!(clock.flagged(start + Duration::from_secs(65)), Some(Color::Black));
+    clock.stop(start + Duration::from_secs(10));
+    assert_eq!(clock.flagged(start + Duration::from_secs(600)), None);
+}
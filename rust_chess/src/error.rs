@@ -0,0 +1,101 @@
+use std::fmt;
+
+use crate::{
+    fen::FenError,
+    game::IllegalMove,
+    pgn::PgnError,
+    piece::{Color, PieceType, Position, square_name},
+    san::SanError,
+    uci::UciError,
+    validate::SetupError,
+};
+
+// The crate-wide error, the notation errors keep their own types and convert into it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChessError {
+    NoKing(Color),
+    NotAKing(Position),
+    EmptySquare(Position),
+    OccupiedSquare(Position),
+    OutOfBoard(Position),
+    // The castle asked for has no king on its home square or no rook in the corner
+    CastlingUnavailable(Color, bool),
+    IllegalPosition(Vec<SetupError>),
+    InvalidChess960Index(usize),
+    // Nothing of that type to drop, or no pocket at all outside Crazyhouse
+    EmptyPocket(Color, PieceType),
+    NoGame,
+    // Bughouse boards are numbered 0 and 1
+    NoSuchBoard(usize),
+    Unsupported(String),
+    // The ai to move returned no move though the game goes on
+    NoAiMove(Color),
+    IllegalMove(IllegalMove),
+    Fen(FenError),
+    Pgn(PgnError),
+    San(SanError),
+    Uci(UciError),
+}
+
+impl fmt::Display for ChessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoKing(c) => write!(f, "no {:?} king on the board", c),
+            Self::NotAKing(pos) => write!(f, "no king on {}", square_name(pos)),
+            Self::EmptySquare(pos) => write!(f, "no piece on {}", square_name(pos)),
+            Self::OccupiedSquare(pos) => write!(f, "{} is already occupied", square_name(pos)),
+            Self::OutOfBoard(pos) => write!(f, "{:?} is out of the board", pos),
+            Self::CastlingUnavailable(c, kingside) => {
+                let side = if *kingside { "kingside" } else { "queenside" };
+                write!(f, "{:?} can't castle {}, its king or rook is not in place", c, side)
+            },
+            Self::IllegalPosition(errors) => {
+                let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                write!(f, "illegal position: {}", errors.join(", "))
+            },
+            Self::InvalidChess960Index(index) => write!(f, "no Chess960 position {}, they go from 0 to 959", index),
+            Self::EmptyPocket(c, ptype) => write!(f, "{:?} has no {:?} in its pocket", c, ptype),
+            Self::NoGame => write!(f, "no game in progress"),
+            Self::NoSuchBoard(board) => write!(f, "no board {}, there are only boards 0 and 1", board),
+            Self::Unsupported(what) => write!(f, "unsupported: {}", what),
+            Self::NoAiMove(c) => write!(f, "the {:?} ai found no move to play", c),
+            Self::IllegalMove(e) => write!(f, "illegal move: {}", e),
+            Self::Fen(e) => e.fmt(f),
+            Self::Pgn(e) => e.fmt(f),
+            Self::San(e) => e.fmt(f),
+            Self::Uci(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for ChessError {}
+
+impl From<IllegalMove> for ChessError {
+    fn from(e: IllegalMove) -> Self {
+        Self::IllegalMove(e)
+    }
+}
+
+impl From<FenError> for ChessError {
+    fn from(e: FenError) -> Self {
+        Self::Fen(e)
+    }
+}
+
+impl From<PgnError> for ChessError {
+    fn from(e: PgnError) -> Self {
+        Self::Pgn(e)
+    }
+}
+
+impl From<SanError> for ChessError {
+    fn from(e: SanError) -> Self {
+        Self::San(e)
+    }
+}
+
+impl From<UciError> for ChessError {
+    fn from(e: UciError) -> Self {
+        Self::Uci(e)
+    }
+}
@@ -0,0 +1,370 @@
+use std::fmt;
+
+use crate::{
+    bitboard::{self, color_index},
+    chessbord::{ChessBoard, Pocket, pawn_start_rank},
+    game::GameEngine,
+    piece::{Color, Piece, PieceCommon, PieceType, Position, parse_square, square_name},
+    validate::SetupError,
+};
+
+// The orthodox starting position, as assumed by PGN files without a FEN tag
+pub const STARTING_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FenError {
+    MissingField(&'static str),
+    TooManyFields,
+    InvalidPlacement(String),
+    InvalidSideToMove(String),
+    InvalidCastling(String),
+    InvalidEnPassant(String),
+    InvalidCounter(String),
+    // The FEN is well formed but the position can't be played
+    IllegalPosition(Vec<SetupError>),
+}
+
+impl fmt::Display for FenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingField(field) => write!(f, "missing FEN field: {}", field),
+            Self::TooManyFields => write!(f, "too many FEN fields"),
+            Self::InvalidPlacement(s) => write!(f, "invalid piece placement: {}", s),
+            Self::InvalidSideToMove(s) => write!(f, "invalid side to move: {}", s),
+            Self::InvalidCastling(s) => write!(f, "invalid castling rights: {}", s),
+            Self::InvalidEnPassant(s) => write!(f, "invalid en passant square: {}", s),
+            Self::InvalidCounter(s) => write!(f, "invalid move counter: {}", s),
+            Self::IllegalPosition(errors) => {
+                let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                write!(f, "illegal position: {}", errors.join(", "))
+            },
+        }
+    }
+}
+
+impl std::error::Error for FenError {}
+
+fn piece_from_fen_char(c: char, pos: Position, id: usize) -> Option<Piece> {
+    let color = if c.is_ascii_uppercase() { Color::White } else { Color::Black };
+    let ptype = match c.to_ascii_lowercase() {
+        'p' => PieceType::Pawn,
+        'n' => PieceType::Knight,
+        'b' => PieceType::Bishop,
+        'r' => PieceType::Rook,
+        'q' => PieceType::Queen,
+        'k' => PieceType::King,
+        _ => return None
+    };
+    Some(Piece::new(pos, color, ptype, id))
+}
+
+fn fen_char(piece: &Piece) -> Option<char> {
+    let c = match piece.get_type()? {
+        PieceType::Pawn => 'p',
+        PieceType::Knight => 'n',
+        PieceType::Bishop => 'b',
+        PieceType::Rook => 'r',
+        PieceType::Queen => 'q',
+        PieceType::King => 'k',
+        PieceType::Empty => return None
+    };
+    match piece.color()? {
+        Color::White => Some(c.to_ascii_uppercase()),
+        Color::Black => Some(c)
+    }
+}
+
+impl ChessBoard {
+    pub fn from_fen(fen: &str) -> Result<Self, FenError> {
+        let fields: Vec<&str> = fen.split_whitespace().collect();
+        let placement = fields.first().ok_or(FenError::MissingField("piece placement"))?;
+        let side = fields.get(1).ok_or(FenError::MissingField("side to move"))?;
+        let castling = fields.get(2).ok_or(FenError::MissingField("castling rights"))?;
+        let en_passant = fields.get(3).ok_or(FenError::MissingField("en passant square"))?;
+        // The move counters are often left out (EPD style), we default them
+        let halfmove = fields.get(4).unwrap_or(&"0");
+        let fullmove = fields.get(5).unwrap_or(&"1");
+        if fields.len() > 6 {
+            return Err(FenError::TooManyFields)
+        }
+
+        // Crazyhouse appends the pockets to the placement, as in [QNpp]
+        let (placement, pockets) = match placement.split_once('[') {
+            Some((placement, pockets)) => {
+                let pockets = pockets.strip_suffix(']').ok_or_else(|| FenError::InvalidPlacement(pockets.to_string()))?;
+                (placement, Some(pockets))
+            },
+            None => (*placement, None)
+        };
+
+        let mut board = Self::new_empty();
+        board.parse_placement(placement)?;
+        if let Some(pockets) = pockets {
+            board.parse_pockets(pockets)?;
+        }
+        board.side_to_move = match *side {
+            "w" => Color::White,
+            "b" => Color::Black,
+            other => return Err(FenError::InvalidSideToMove(other.into()))
+        };
+        board.collect_factions();
+        board.parse_castling(castling)?;
+        board.parse_en_passant(en_passant)?;
+        board.halfmove_clock = halfmove.parse().map_err(|_| FenError::InvalidCounter(halfmove.to_string()))?;
+        board.fullmove_number = fullmove.parse().map_err(|_| FenError::InvalidCounter(fullmove.to_string()))?;
+        if board.fullmove_number == 0 {
+            return Err(FenError::InvalidCounter(fullmove.to_string()))
+        }
+        board.refresh_hash();
+        Ok(board)
+    }
+
+    fn parse_placement(&mut self, placement: &str) -> Result<(), FenError> {
+        let err = || FenError::InvalidPlacement(placement.to_string());
+        let rows: Vec<&str> = placement.split('/').collect();
+        if rows.len() != 8 {
+            return Err(err())
+        }
+        let mut next_id = 1;
+        for (i, row) in rows.iter().enumerate() {
+            let mut j: usize = 0;
+            for c in row.chars() {
+                // A promoted piece is followed by a tilde, in Crazyhouse
+                if c == '~' {
+                    let promoted = j.checked_sub(1).filter(|col| !self.board[i][*col].is_empty()).ok_or_else(err)?;
+                    self.promoted |= bitboard::bit(&(i as i8, promoted as i8));
+                    continue
+                }
+                if let Some(n) = c.to_digit(10) {
+                    if n == 0 || n > 8 {
+                        return Err(err())
+                    }
+                    j += n as usize;
+                    continue
+                }
+                if j >= 8 {
+                    return Err(err())
+                }
+                let pos = (i as i8, j as i8);
+                let mut piece = piece_from_fen_char(c, pos, next_id).ok_or_else(err)?;
+                // Castling rights decide later which kings and rooks are still unmoved
+                let color = piece.color().unwrap();
+                let has_moved = match piece.get_type() {
+                    Some(PieceType::Pawn) => pos.0 != pawn_start_rank(&color),
+                    _ => true
+                };
+                piece.set_has_moved(has_moved);
+                self.board[i][j] = piece;
+                next_id += 1;
+                j += 1;
+            }
+            if j != 8 {
+                return Err(err())
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_pockets(&mut self, pockets: &str) -> Result<(), FenError> {
+        let mut parsed: [Pocket; 2] = Default::default();
+        for c in pockets.chars() {
+            let piece = piece_from_fen_char(c, (0, 0), 0).ok_or_else(|| FenError::InvalidPlacement(pockets.to_string()))?;
+            let (color, ptype) = (piece.color().unwrap(), piece.get_type().unwrap());
+            if ptype == PieceType::King {
+                return Err(FenError::InvalidPlacement(pockets.to_string()))
+            }
+            parsed[color_index(&color)].add(&ptype);
+        }
+        self.pockets = Some(parsed);
+        Ok(())
+    }
+
+    fn parse_castling(&mut self, castling: &str) -> Result<(), FenError> {
+        if castling == "-" {
+            return Ok(())
+        }
+        let err = || FenError::InvalidCastling(castling.to_string());
+        let mut seen = vec!();
+        for c in castling.chars() {
+            let color = if c.is_ascii_uppercase() { Color::White } else { Color::Black };
+            // KQkq name the outermost rook (X-FEN), a file letter names the rook itself (Shredder-FEN)
+            let (kingside, file) = match c.to_ascii_lowercase() {
+                'k' => (true, self.outermost_rook(&color, true).ok_or_else(err)?),
+                'q' => (false, self.outermost_rook(&color, false).ok_or_else(err)?),
+                f @ 'a'..='h' => {
+                    let file = f as i8 - 'a' as i8;
+                    let king_file = self.locate_king(&color).map_err(|_| err())?.1;
+                    (file > king_file, file)
+                },
+                _ => return Err(err())
+            };
+            if seen.contains(&(color.clone(), kingside)) {
+                return Err(err())
+            }
+            seen.push((color.clone(), kingside));
+            self.set_castling_rook(&color, kingside, file).map_err(|_| err())?;
+            let (king_pos, _, rook_pos, _) = self.castling_squares(&color, kingside).map_err(|_| err())?;
+            if king_pos.1 != 4 || rook_pos.1 != if kingside { 7 } else { 0 } {
+                self.chess960 = true;
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_en_passant(&mut self, en_passant: &str) -> Result<(), FenError> {
+        if en_passant == "-" {
+            return Ok(())
+        }
+        let err = || FenError::InvalidEnPassant(en_passant.to_string());
+        let target = parse_square(en_passant).ok_or_else(err)?;
+        // The target square is behind the pawn that just made its double step
+        let (pawn_pos, pawn_color) = match (target.0, &self.side_to_move) {
+            (5, Color::Black) => ((4, target.1), Color::White),
+            (2, Color::White) => ((3, target.1), Color::Black),
+            _ => return Err(err())
+        };
+        match self.board[pawn_pos.0 as usize][pawn_pos.1 as usize] {
+            Piece::Pawn(ref p) if p.color() == pawn_color => {},
+            _ => return Err(err())
+        }
+        self.en_passant = Some(target);
+        Ok(())
+    }
+
+    pub fn to_fen(&self) -> String {
+        let mut rows = vec!();
+        for i in 0..8 {
+            let mut row = String::new();
+            let mut empty = 0;
+            for j in 0..8 {
+                match fen_char(&self.board[i][j]) {
+                    Some(c) => {
+                        if empty > 0 {
+                            row.push_str(&empty.to_string());
+                            empty = 0;
+                        }
+                        row.push(c);
+                        if self.pockets.is_some() && self.promoted & bitboard::bit(&(i as i8, j as i8)) != 0 {
+                            row.push('~');
+                        }
+                    },
+                    None => empty += 1
+                }
+            }
+            if empty > 0 {
+                row.push_str(&empty.to_string());
+            }
+            rows.push(row);
+        }
+        let side = match self.side_to_move {
+            Color::White => "w",
+            Color::Black => "b"
+        };
+        let en_passant = match self.en_passant {
+            Some(target) => square_name(&target),
+            None => "-".into()
+        };
+        let mut placement = rows.join("/");
+        if let Some(pockets) = &self.pockets {
+            placement.push('[');
+            for (color, pocket) in [Color::White, Color::Black].iter().zip(pockets) {
+                for ptype in Pocket::PIECES.iter().rev() {
+                    let piece = Piece::new((0, 0), color.clone(), ptype.clone(), 0);
+                    let c = fen_char(&piece).unwrap();
+                    placement.extend(std::iter::repeat_n(c, pocket.count(ptype) as usize));
+                }
+            }
+            placement.push(']');
+        }
+        format!(
+            "{} {} {} {} {} {}",
+            placement,
+            side,
+            self.castling_fen(),
+            en_passant,
+            self.halfmove_clock,
+            self.fullmove_number
+        )
+    }
+
+    fn castling_fen(&self) -> String {
+        let mut castling = String::new();
+        for color in [Color::White, Color::Black] {
+            for kingside in [true, false] {
+                if !self.castling_rights.has(&color, kingside) {
+                    continue
+                }
+                // X-FEN: the file letter is only needed when another rook stands further out
+                let file = self.castling_file(&color, kingside);
+                let symbol = match self.outermost_rook(&color, kingside) {
+                    Some(outermost) if outermost == file => if kingside { 'k' } else { 'q' },
+                    _ => (b'a' + file as u8) as char
+                };
+                castling.push(match color {
+                    Color::White => symbol.to_ascii_uppercase(),
+                    Color::Black => symbol
+                });
+            }
+        }
+        if castling.is_empty() {
+            castling.push('-');
+        }
+        castling
+    }
+}
+
+impl GameEngine {
+    // Unlike the board, a game only starts from a legal position
+    pub fn from_fen(fen: &str) -> Result<Self, FenError> {
+        let board = ChessBoard::from_fen(fen)?;
+        board.validate().map_err(FenError::IllegalPosition)?;
+        Ok(Self::from_board(board))
+    }
+
+    pub fn to_fen(&self) -> String {
+        self.board.to_fen()
+    }
+}
+
+
+#[test]
+fn test_fen_round_trip() {
+    let fens = [
+        STARTING_FEN,
+        "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq e6 0 2",
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        "r3k2r/8/8/8/8/8/8/R3K2R b Kq - 12 40",
+    ];
+    for fen in fens {
+        let engine = GameEngine::from_fen(fen).unwrap();
+        assert_eq!(engine.to_fen(), fen);
+    }
+    assert_eq!(GameEngine::new().to_fen(), STARTING_FEN);
+    assert!(ChessBoard::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBN w KQkq - 0 1").is_err());
+    assert!(ChessBoard::from_fen("8/8/8/8/8/8/8/8 w K - 0 1").is_err());
+}
+
+#[test]
+fn test_castling_rights_and_en_passant() {
+    use crate::piece::Move;
+    // Taking a rook on its corner removes the castle, moving the king removes both
+    let mut engine = GameEngine::from_fen("r3k2r/8/8/8/8/8/6b1/R3K2R b KQkq - 0 1").unwrap();
+    let undo = engine.make_move(&Move::Take((6, 6), (7, 7))).unwrap();
+    assert_eq!(engine.board.castling_rights.0, 0b1110);
+    assert!(engine.to_fen().contains(" w Qkq - "));
+    engine.unmake_move(undo);
+    assert_eq!(engine.board.castling_rights, crate::chessbord::CastlingRights::ALL);
+    engine.make_move(&Move::Take((6, 6), (7, 7))).unwrap();
+    engine.make_move(&Move::Move((7, 4), (7, 3))).unwrap();
+    assert!(engine.to_fen().contains(" b kq - "));
+    engine.make_move(&Move::Move((0, 0), (1, 0))).unwrap();
+    assert!(engine.to_fen().contains(" w k - "));
+    // A double step sets the en passant square for one ply only
+    let mut engine = GameEngine::from_fen(STARTING_FEN).unwrap();
+    engine.make_move(&Move::Move((6, 4), (4, 4))).unwrap();
+    assert_eq!(engine.board.en_passant, Some((5, 4)));
+    assert!(engine.to_fen().contains(" b KQkq e3 "));
+    engine.make_move(&Move::Move((0, 6), (2, 5))).unwrap();
+    assert_eq!(engine.board.en_passant, None);
+}
@@ -1,97 +1,422 @@
+use std::borrow::Cow;
 use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use actix::Message;
 use serde::{Serialize, Deserialize};
 
 use crate::{
-    chessbord::{ChessBoard, WebappRepr, apply_markers},
-    piece::{Color, Move, Piece, Position, King, PieceType, CanPromoteTo}, ai::Ai,
+    chessbord::{BoardUndo, ChessBoard, Pocket, WebappRepr, apply_markers},
+    piece::{Color, Move, Position, PieceType, CanPromoteTo, square_name}, ai::Ai,
+    bitboard,
+    pgn::write_pgn,
+    error::ChessError,
+    variant::{Bughouse, Standard, Variant},
+    clock::Clock,
 };
 
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum GameOutcome {
+    Checkmate { winner: Color },
+    Stalemate,
+    ThreefoldRepetition,
+    FiftyMoveRule,
+    InsufficientMaterial,
+    // Variant wins: a king on a centre square, a third check, a king first on the 8th rank
+    HillReached { winner: Color },
+    ThirdCheck { winner: Color },
+    RaceWon { winner: Color },
+    // Both kings made it to the 8th rank
+    RaceTied,
+    // The loser's king was caught in an explosion, in Atomic
+    KingExploded { winner: Color },
+    // The side to move has no piece or no move left, which wins Antichess
+    NoMovesLeft { winner: Color },
+    // The loser ran out of time
+    TimeOut { winner: Color },
+    // The loser's king was taken, in Fog of war
+    KingCaptured { winner: Color },
+}
+
+impl GameOutcome {
+    pub fn winner(&self) -> Option<Color> {
+        match self {
+            Self::Checkmate { winner }
+            | Self::HillReached { winner }
+            | Self::ThirdCheck { winner }
+            | Self::RaceWon { winner }
+            | Self::KingExploded { winner }
+            | Self::NoMovesLeft { winner }
+            | Self::KingCaptured { winner }
+            | Self::TimeOut { winner } => Some(winner.clone()),
+            _ => None
+        }
+    }
+}
+
+// Why `GameEngine::try_play` rejected a move
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IllegalMove {
+    GameOver,
+    NoPiece(Position),
+    WrongSideToMove(Color),
+    PiecePinned(Position),
+    KingInCheck,
+    // A pawn move onto this square has to be a `Move::Promote`
+    MissingPromotion(Position),
+    NoPromotionPending,
+    InvalidMove(Move),
+    // The side to move is played by an ai, the players can't move for it
+    NotYourTurn(Color),
+}
+
+impl fmt::Display for IllegalMove {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::GameOver => write!(f, "the game is over"),
+            Self::NoPiece(pos) => write!(f, "no piece on {}", square_name(pos)),
+            Self::WrongSideToMove(c) => write!(f, "it is {:?}'s turn to move", c),
+            Self::PiecePinned(pos) => write!(f, "the piece on {} is pinned", square_name(pos)),
+            Self::KingInCheck => write!(f, "the move leaves the king in check"),
+            Self::MissingPromotion(pos) => write!(f, "the pawn reaching {} has to name its promotion piece", square_name(pos)),
+            Self::NoPromotionPending => write!(f, "no pawn is waiting for a promotion"),
+            Self::InvalidMove(m) => write!(f, "the piece can't play {:?}", m),
+            Self::NotYourTurn(c) => write!(f, "{:?} is played by an ai", c),
+        }
+    }
+}
+
+impl std::error::Error for IllegalMove {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MoveOutcome {
+    Played { check: bool },
+    GameOver(GameOutcome),
+}
+
+// Everything `GameEngine::make_move` changes, as it was before the ply
+#[derive(Clone, Debug)]
+pub struct MoveUndo {
+    board: BoardUndo,
+    controlled: HashSet<Position>,
+    current_player: Color,
+    turn: usize,
+    check: bool,
+    attack_vector: Vec<HashSet<Position>>
+}
+
 #[derive(Clone, Debug)]
 pub struct GameEngine {
     pub board: ChessBoard,
     pub board_history: Vec<ChessBoard>,
+    pub move_history: Vec<Move>,
     pub current_player: Color,
     pub current_history_offset: usize,
     pub turn: usize,
-    pub promotion_opt: Option<(i8, i8)>,
     pub check: bool,
     pub checkmate: bool,
-    pub attack_vector: Vec<HashSet<Position>>
+    pub attack_vector: Vec<HashSet<Position>>,
+    // Hash of every position reached, the current one included
+    pub position_history: Vec<u64>,
+    pub outcome: Option<GameOutcome>,
+    // The rules being played, shared with every copy of the engine
+    pub variant: Arc<dyn Variant>
 }
 
 impl GameEngine {
     pub fn new() -> Self {
+        let board = ChessBoard::new_default();
+        let position_history = vec![board.position_hash()];
         Self {
-            board: ChessBoard::new_default(),
+            board: board,
             board_history: vec!(),
+            move_history: vec!(),
             current_player: Color::White,
             current_history_offset: 0,
             turn: 0,
-            promotion_opt: None,
             check: false,
             checkmate: false,
-            attack_vector: vec!()
+            attack_vector: vec!(),
+            position_history: position_history,
+            outcome: None,
+            variant: Arc::new(Standard)
         }
     }
 
+    pub fn with_variant(variant: Arc<dyn Variant>) -> Self {
+        let board = variant.starting_board();
+        Self::from_board_and_variant(board, variant)
+    }
+
+    // Builds an engine around any board, the board's side to move plays next
+    pub fn from_board(board: ChessBoard) -> Self {
+        Self::from_board_and_variant(board, Arc::new(Standard))
+    }
+
+    pub fn from_board_and_variant(board: ChessBoard, variant: Arc<dyn Variant>) -> Self {
+        let mut engine = Self::new();
+        engine.variant = variant;
+        engine.current_player = board.side_to_move.clone();
+        engine.position_history = vec![board.position_hash()];
+        engine.board = board;
+        // Nobody played yet, so both factions need their controlled squares before the first turn
+        engine.board.update_controlled_squares(&Color::White);
+        engine.board.update_controlled_squares(&Color::Black);
+        engine.prepare_new_turn();
+        engine
+    }
+
     pub fn get_moves_for_piece(&self, pos: &Position) -> HashMap<Position, Move> {
-        let dst = &self.board.board[pos.0 as usize][pos.1 as usize];
-        dst.gen_moves_check(&self.board, self.check, &self.attack_vector)
+        // Castling belongs to the king, the promotions of a pawn share their square and only one of them is kept
+        self.gen_all_moves()
             .into_iter()
-            .map(|m| (m.to(), m))
-            .filter(|(to, _)| to.is_some())
-            .map(|(to, m)| (to.unwrap(), m))
+            .filter(|m| m.uci_squares(&self.board).map(|(from, _)| from) == Some(*pos))
+            .filter_map(|m| m.to().map(|to| (to, m)))
             .collect()
     }
 
-    pub fn get_board_as_key(&mut self) -> String {
-        self.board.get_board_as_key()
-    }
-
-    pub fn rollback(&mut self) {
+    // Takes back the last move and returns it, None at the start of the game
+    pub fn rollback(&mut self) -> Option<Move> {
+        self.board = self.board_history.pop()?;
         self.turn -= 1;
-        let board_rb = self.board_history.pop().unwrap();
-        self.board = board_rb;
+        self.current_player = self.current_player.other();
+        self.position_history.pop();
+        self.outcome = None;
+        self.prepare_new_turn();
+        self.move_history.pop()
     }
 
     pub fn to_webapp(&self) -> WebappRepr {
         self.board.to_webapp()
     }
 
+    // `board` (the current one or one of the history) as shown to `viewer`, the variant may hide part of it
+    pub fn to_webapp_for(&self, board: &ChessBoard, viewer: &Color) -> WebappRepr {
+        match self.variant.visible_squares(board, viewer) {
+            Some(visible) => board.to_webapp_masked(visible),
+            None => board.to_webapp()
+        }
+    }
+
+    // The game as the ai of the player to move may know it
+    pub fn ai_view(&self) -> Cow<'_, GameEngine> {
+        match self.variant.player_view(self, &self.current_player) {
+            Some(view) => Cow::Owned(view),
+            None => Cow::Borrowed(self)
+        }
+    }
+
+    // Computes the check state of the player to move, with the squares of each attack (checker included)
     pub fn prepare_new_turn(&mut self) {
-        self.board.reset_pin_vectors(&self.current_player);
-        let king_pos = self.board.locate_king(&self.current_player);
-        King::gen_attack_vectors(king_pos, &mut self.board);
-        let king = match self.board.board[king_pos.0 as usize][king_pos.1 as usize] {
-            Piece::King(ref k) => k,
-            _ => panic!("No king found")
-        };
-        self.attack_vector = king.direct_attacks.clone();
-        self.check = king.check;
+        self.attack_vector = self.board.attack_vectors(&self.current_player);
+        self.check = !self.attack_vector.is_empty();
     }
 
     pub fn finish_turn(&mut self) {
         self.board.update_controlled_squares(&self.current_player);
         self.turn += 1;
         self.current_player = self.current_player.other();
+        self.position_history.push(self.board.position_hash());
+    }
+
+    // The board was changed from outside a move (the pockets of Bughouse), the current position gets its new hash
+    pub fn refresh_position(&mut self) {
+        self.board.refresh_hash();
+        if let Some(current) = self.position_history.last_mut() {
+            *current = self.board.position_hash();
+        }
+    }
+
+    // Plays a ply without touching the game history, to be taken back with `unmake_move`
+    pub fn make_move(&mut self, m: &Move) -> Result<MoveUndo, ChessError> {
+        let extra = self.variant.side_effect_squares(&self.board, m);
+        let captured = self.board.captured(m);
+        let board = self.board.make_move_saving(m.clone(), &extra)?;
+        if let Err(e) = self.variant.after_move(&mut self.board, m, captured.as_ref()) {
+            self.board.unmake_move(board);
+            return Err(e)
+        }
+        let controlled = match self.current_player {
+            Color::White => std::mem::take(&mut self.board.faction.white_controlled),
+            Color::Black => std::mem::take(&mut self.board.faction.black_controlled)
+        };
+        let current_player = self.current_player.clone();
+        let turn = self.turn;
+        let check = self.check;
+        let attack_vector = std::mem::take(&mut self.attack_vector);
+        self.finish_turn();
+        self.prepare_new_turn();
+        Ok(MoveUndo {
+            board: board,
+            controlled: controlled,
+            current_player: current_player,
+            turn: turn,
+            check: check,
+            attack_vector: attack_vector
+        })
+    }
+
+    pub fn unmake_move(&mut self, undo: MoveUndo) {
+        self.position_history.pop();
+        self.current_player = undo.current_player;
+        self.turn = undo.turn;
+        self.check = undo.check;
+        self.attack_vector = undo.attack_vector;
+        match self.current_player {
+            Color::White => self.board.faction.white_controlled = undo.controlled,
+            Color::Black => self.board.faction.black_controlled = undo.controlled
+        }
+        self.board.unmake_move(undo.board);
+    }
+
+    pub fn is_threefold_repetition(&self) -> bool {
+        match self.position_history.last() {
+            Some(current) => self.position_history.iter().filter(|h| *h == current).count() >= 3,
+            None => false
+        }
+    }
+
+    pub fn is_fifty_move_rule(&self) -> bool {
+        self.board.halfmove_clock >= 100
+    }
+
+    // Has to be called once the turn is prepared, since it relies on the check state
+    pub fn compute_outcome(&self) -> Option<GameOutcome> {
+        self.variant.outcome(self, &self.gen_all_moves())
+    }
+
+    // The orthodox rules, given the legal moves of the player to move
+    pub fn standard_outcome(&self, moves: &[Move]) -> Option<GameOutcome> {
+        if moves.is_empty() {
+            return match self.check {
+                true => Some(GameOutcome::Checkmate { winner: self.current_player.other() }),
+                false => Some(GameOutcome::Stalemate)
+            }
+        }
+        if self.is_threefold_repetition() {
+            Some(GameOutcome::ThreefoldRepetition)
+        }
+        else if self.is_fifty_move_rule() {
+            Some(GameOutcome::FiftyMoveRule)
+        }
+        else if self.board.has_insufficient_material() {
+            Some(GameOutcome::InsufficientMaterial)
+        }
+        else {
+            None
+        }
+    }
+
+    pub fn update_outcome(&mut self) {
+        self.outcome = self.compute_outcome();
+        self.checkmate = matches!(self.outcome, Some(GameOutcome::Checkmate { .. }));
     }
 
     // This function allow to plug IA into the engine, they will be trusted and bypass move legality checking
-    // If the IA does weird stuff, it could block the whole system
-    pub fn play_bypass(&mut self, moves: Vec<Move>) {
+    // If the IA does weird stuff, it could block the whole system, `try_play` is the checked alternative
+    pub fn play_bypass(&mut self, m: Move) -> Result<(), ChessError> {
         let curr_board = self.board.clone();
+        let captured = self.board.captured(&m);
+        self.board.play_once(m.clone())?;
+        if let Err(e) = self.variant.after_move(&mut self.board, &m, captured.as_ref()) {
+            self.board = curr_board;
+            return Err(e)
+        }
         self.board_history.push(curr_board);
-        for m in moves {
-            self.board.play_once(m);
+        self.move_history.push(m);
+        Ok(())
+    }
+
+    // Checks and plays a move from an untrusted source, ending the turn once the ply is complete
+    pub fn try_play(&mut self, m: Move) -> Result<MoveOutcome, ChessError> {
+        if self.outcome.is_some() {
+            return Err(IllegalMove::GameOver.into())
         }
+        self.validate_move(&m)?;
+        self.play_bypass(m)?;
+        Ok(self.end_turn())
     }
 
-    pub fn play_once(&mut self, m: Move) -> Option<Position> {
-        let curr_board = self.board.clone();
-        self.board_history.push(curr_board);
-        self.board.play_once(m)
+    fn end_turn(&mut self) -> MoveOutcome {
+        self.finish_turn();
+        self.prepare_new_turn();
+        self.update_outcome();
+        match self.outcome {
+            Some(ref outcome) => MoveOutcome::GameOver(outcome.clone()),
+            None => MoveOutcome::Played { check: self.check }
+        }
+    }
+
+    pub fn validate_move(&self, m: &Move) -> Result<(), IllegalMove> {
+        let from = match m {
+            Move::KingsideCastle(c) | Move::QueensideCastle(c) if *c != self.current_player => {
+                return Err(IllegalMove::WrongSideToMove(self.current_player.clone()))
+            },
+            Move::KingsideCastle(c) | Move::QueensideCastle(c) => {
+                self.board.locate_king(c).map_err(|_| IllegalMove::InvalidMove(m.clone()))?
+            },
+            Move::Drop(c, _, _) if *c != self.current_player => {
+                return Err(IllegalMove::WrongSideToMove(self.current_player.clone()))
+            },
+            // Drops never expose the king, the ones not generated are simply invalid
+            Move::Drop(_, _, _) if self.gen_all_moves().contains(m) => return Ok(()),
+            Move::Drop(_, _, _) if self.check => return Err(IllegalMove::KingInCheck),
+            Move::Drop(_, _, _) => return Err(IllegalMove::InvalidMove(m.clone())),
+            _ => m.from().ok_or_else(|| IllegalMove::InvalidMove(m.clone()))?
+        };
+        if !(0..8).contains(&from.0) || !(0..8).contains(&from.1) {
+            return Err(IllegalMove::NoPiece(from))
+        }
+        let piece = &self.board.board[from.0 as usize][from.1 as usize];
+        match piece.color() {
+            None => return Err(IllegalMove::NoPiece(from)),
+            Some(c) if c != self.current_player => return Err(IllegalMove::WrongSideToMove(self.current_player.clone())),
+            _ => {}
+        }
+        let legal_moves = self.gen_all_moves();
+        if legal_moves.contains(m) {
+            return Ok(())
+        }
+        // The pawn move exists, but only along with its promotion piece
+        if m.promotion().is_none() {
+            let promotion_square = legal_moves.iter()
+                .filter(|l| l.promotion().is_some() && l.from() == m.from())
+                .find_map(|l| l.to().filter(|to| Some(*to) == m.to()));
+            if let Some(to) = promotion_square {
+                return Err(IllegalMove::MissingPromotion(to))
+            }
+        }
+        // The piece could play the move, had it not to protect its king
+        let en_passant = self.board.en_passant_target(&self.current_player);
+        let is_castle = matches!(m, Move::KingsideCastle(_) | Move::QueensideCastle(_));
+        let pseudo_legal = self.board.bitboards.pseudo_legal_moves(&self.current_player, en_passant).contains(m);
+        let could_play = pseudo_legal || (is_castle && self.check);
+        if !could_play {
+            return Err(IllegalMove::InvalidMove(m.clone()))
+        }
+        match piece.get_type() {
+            Some(PieceType::King) => Err(IllegalMove::KingInCheck),
+            _ if self.is_pinned(&from) => Err(IllegalMove::PiecePinned(from)),
+            _ if self.check => Err(IllegalMove::KingInCheck),
+            // Ruled out by the variant, as a non capture when Antichess has captures to play
+            _ => Err(IllegalMove::InvalidMove(m.clone()))
+        }
+    }
+
+    // Removing the piece would expose its king to a new attacker
+    pub fn is_pinned(&self, pos: &Position) -> bool {
+        let bitboards = &self.board.bitboards;
+        let king = match bitboards.king_square(&self.current_player) {
+            Some(king) => king,
+            None => return false
+        };
+        let them = self.current_player.other();
+        let occupied = bitboards.occupied();
+        let before = bitboards.attackers(king, &them, occupied);
+        let after = bitboards.attackers(king, &them, occupied & !bitboard::bit(pos));
+        after & !before != 0
     }
 
     pub fn is_current_player_piece(&self, pos: &Position) -> bool {
@@ -102,14 +427,14 @@ impl GameEngine {
     }
 
     pub fn gen_all_moves(&self) -> Vec<Move> {
-        self.board.gen_all_moves(&self.current_player, self.check, &self.attack_vector)
+        self.variant.legal_moves(&self.board, &self.current_player)
     }
 }
 
 pub struct PlayerVsPlayer {
     game_engine: GameEngine,
     current_selection: Option<Position>,
-    can_promote: Option<Position>
+    pending_promotion: Option<Move>
 }
 
 
@@ -118,119 +443,155 @@ pub struct PlayerVsIa {
     player_color: Color,
     current_selection: Option<Position>,
     current_moves: HashMap<Position, Move>,
-    can_promote: Option<Position>,
+    // The promotion the player chose the square of, waiting for its piece
+    pending_promotion: Option<Move>,
     ai: Box<dyn Ai>
 }
 
 impl PlayerVsIa {
-    pub fn new(player_color: Color, ai: Box<dyn Ai>) -> Self {
-        Self {
-            game_engine: GameEngine::new(),
+    pub fn new(player_color: Color, ai: Box<dyn Ai>) -> Result<Self, ChessError> {
+        Self::from_board(player_color, ai, ChessBoard::new_default())
+    }
+
+    pub fn from_board(player_color: Color, ai: Box<dyn Ai>, board: ChessBoard) -> Result<Self, ChessError> {
+        Self::from_engine(player_color, ai, GameEngine::from_board(board))
+    }
+
+    // The ai opens if the engine has it to move
+    pub fn from_engine(player_color: Color, ai: Box<dyn Ai>, game_engine: GameEngine) -> Result<Self, ChessError> {
+        let mut game = Self {
+            game_engine: game_engine,
             player_color: player_color,
             current_selection: None,
             current_moves: HashMap::new(),
-            can_promote: None,
+            pending_promotion: None,
             ai: ai
+        };
+        game.game_engine.update_outcome();
+        if game.game_engine.current_player != game.player_color {
+            game.ai_play()?;
         }
+        Ok(game)
     }
 }
 
 impl PlayerVsIa {
-    pub fn ai_play(&mut self) {
-        let ai_moves = self.ai.play(&self.game_engine);
-        if ai_moves.len() == 0 {
-            if self.game_engine.check {
-                println!("Checkmate !!")
-            }
-            else {
-                println!("draw !")
-            }
-        }
-        else {
-            self.game_engine.play_bypass(ai_moves);
-            self.game_engine.finish_turn();
-            self.game_engine.prepare_new_turn();
+    // The ai is not trusted, a rejected or missing move is reported and leaves its turn to play
+    pub fn ai_play(&mut self) -> Result<(), ChessError> {
+        if self.game_engine.outcome.is_some() {
+            return Ok(())
         }
+        let m = self.ai.play(&self.game_engine.ai_view())
+            .ok_or_else(|| ChessError::NoAiMove(self.game_engine.current_player.clone()))?;
+        self.game_engine.try_play(m)?;
+        Ok(())
+    }
+
+    fn player_play(&mut self, m: Move) -> Result<(), ChessError> {
+        // The promotion piece and drops come from the player, the variant may not allow them
+        self.game_engine.validate_move(&m)?;
+        self.game_engine.play_bypass(m)?;
+        self.game_engine.finish_turn();
+        self.game_engine.prepare_new_turn();
+        self.game_engine.update_outcome();
+        self.pending_promotion = None;
+        self.current_selection = None;
+        self.current_moves.clear();
+        // The ai plays after the player
+        self.ai_play()
     }
 }
 
 impl Game for PlayerVsIa {
-    fn play(&mut self, m: Play) {
-        // If its the Ai turn to play, we juste ignore the player input
-        if self.game_engine.current_player != self.player_color {
-            return
-        }
-        // If the player could promote but didn't, we have to rollback to his previous move
-        if self.can_promote.is_some() {
-            self.game_engine.rollback();
-            self.can_promote = None;
+    fn play(&mut self, play: Play) -> Result<(), ChessError> {
+        // If its the Ai turn to play or the game is over, we juste ignore the player input
+        if self.game_engine.current_player != self.player_color || self.game_engine.outcome.is_some() {
+            return Ok(())
         }
+        // If the player could promote but didn't, the promotion is given up
+        self.pending_promotion = None;
         // If the play is out of bounds, we reset (we do not need to check for negative since its usize)
-        if m.x > 7 || m.y > 7 {
+        let pos = (play.x as i8, play.y as i8);
+        if play.x > 7 || play.y > 7 {
             self.current_moves.clear();
             self.current_selection = None;
+            return Err(ChessError::OutOfBoard(pos))
+        }
+        // Drops need no selection, the piece comes with the square
+        if let Some(ptype) = play.drop {
+            self.player_play(Move::Drop(self.player_color.clone(), ptype, pos))?;
         }
-        let pos = (m.x as i8, m.y as i8);
         // If the player selects a move
-        if let Some(m) =  self.current_moves.get(&pos) {
-            self.can_promote = self.game_engine.play_once(m.clone());
-            // We only finish the turn if the player can't promote
-            if self.can_promote.is_none() {
-                self.game_engine.finish_turn();
-                self.game_engine.prepare_new_turn();
-                // The ai plays after the player
-                self.ai_play();
+        else if let Some(m) =  self.current_moves.get(&pos).cloned() {
+            match (m.promotion(), play.promote_to) {
+                // Without its piece, the promotion waits for the `Promote` that follows
+                (Some(_), None) => {
+                    self.pending_promotion = Some(m);
+                    self.current_selection = None;
+                    self.current_moves.clear();
+                },
+                (Some(_), Some(p)) => self.player_play(m.with_promotion(p))?,
+                (None, _) => self.player_play(m)?
             }
-            self.current_selection = None;
-            self.current_moves.clear();
         }
         // If the player select one of his pieces
         else if self.game_engine.is_current_player_piece(&pos) {
             self.current_selection = Some(pos.clone());
             self.current_moves = self.game_engine.get_moves_for_piece(&pos);
         }
+        Ok(())
     }
 
-    fn promote(&mut self, p: Promote) {
-        if let Some(pr) = self.can_promote {
-            let m = Move::Promote(pr, p.promote_to.into());
-            self.game_engine.play_once(m);
-            self.game_engine.finish_turn();
-            self.game_engine.prepare_new_turn();
-            self.can_promote = None;
-            self.current_selection = None;
-            self.current_moves.clear();
-            // The ai plays after the player
-            self.ai_play();
-        }
+    fn promote(&mut self, p: Promote) -> Result<(), ChessError> {
+        let m = self.pending_promotion.take().ok_or(IllegalMove::NoPromotionPending)?;
+        self.player_play(m.with_promotion(p.promote_to))
     }
 
+    // Only what the player is allowed to see is sent
     fn webapp_repr(&self) -> GameWebappRepr {
-        let mut board_repr = self.game_engine.to_webapp();
+        let engine = &self.game_engine;
+        let mut board_repr = engine.to_webapp_for(&engine.board, &self.player_color);
         if !self.current_moves.is_empty() {
             apply_markers(&mut board_repr, &self.current_moves.values().cloned().collect())
         }
-        let board_history = self.game_engine.board_history.iter().map(|b| b.to_webapp()).collect();
+        let board_history = engine.board_history.iter().map(|b| engine.to_webapp_for(b, &self.player_color)).collect();
         GameWebappRepr {
             current_player: self.game_engine.current_player.clone(),
             turn: self.game_engine.turn,
             board: board_repr,
             board_history: board_history,
+            outcome: self.game_engine.outcome.clone(),
+            checks: self.game_engine.board.checks,
+            pockets: self.game_engine.board.pockets,
+        }
+    }
+
+    fn to_pgn(&self) -> Result<String, ChessError> {
+        let ai_name = self.ai.name();
+        match self.player_color {
+            Color::White => write_pgn(&self.game_engine, "Player", &ai_name),
+            Color::Black => write_pgn(&self.game_engine, &ai_name, "Player")
         }
     }
 }
 
 
 #[derive(Serialize, Deserialize, Message)]
-#[rtype(result="Result<GameWebappRepr, ()>")]
+#[rtype(result="Result<GameWebappRepr, ChessError>")]
 pub struct Play {
     x: usize,
-    y: usize
+    y: usize,
+    // Lets a promotion be played in a single request
+    #[serde(default)]
+    promote_to: Option<CanPromoteTo>,
+    // A piece of the pocket to drop on the square, in Crazyhouse
+    #[serde(default)]
+    drop: Option<PieceType>
 }
 
 
 #[derive(Serialize, Deserialize, Message)]
-#[rtype(result="Result<GameWebappRepr, ()>")]
+#[rtype(result="Result<GameWebappRepr, ChessError>")]
 pub struct Promote {
     promote_to: CanPromoteTo
 }
@@ -249,48 +610,51 @@ pub struct AiVsAi {
 
 impl AiVsAi {
     pub fn new(black_ai: Box<dyn Ai>, white_ai: Box<dyn Ai>) -> Self {
-        Self { game_engine: GameEngine::new(), black_ai: black_ai, white_ai: white_ai }
+        Self::from_board(black_ai, white_ai, ChessBoard::new_default())
     }
-    pub fn ai_play(&mut self) {
-        let ai_moves = match self.game_engine.current_player {
-            Color::Black => self.black_ai.play(&self.game_engine),
-            Color::White => self.white_ai.play(&self.game_engine)
-        };
-        if ai_moves.len() == 0 {
-            if self.game_engine.check {
-                println!("Checkmate !!")
-            }
-            else {
-                println!("DRAWWW")
-            }
+
+    pub fn from_board(black_ai: Box<dyn Ai>, white_ai: Box<dyn Ai>, board: ChessBoard) -> Self {
+        Self::from_engine(black_ai, white_ai, GameEngine::from_board(board))
+    }
+
+    pub fn from_engine(black_ai: Box<dyn Ai>, white_ai: Box<dyn Ai>, mut game_engine: GameEngine) -> Self {
+        game_engine.update_outcome();
+        Self { game_engine: game_engine, black_ai: black_ai, white_ai: white_ai }
+    }
+    // The ais are not trusted, a rejected move is reported and leaves their turn to play
+    pub fn ai_play(&mut self) -> Result<(), ChessError> {
+        if self.game_engine.outcome.is_some() {
+            return Ok(())
         }
-        else {
-            self.game_engine.play_bypass(ai_moves);
-            self.game_engine.finish_turn();
-            self.game_engine.prepare_new_turn();
-        }   
+        let view = self.game_engine.ai_view();
+        let ai_move = match self.game_engine.current_player {
+            Color::Black => self.black_ai.play(&view),
+            Color::White => self.white_ai.play(&view)
+        };
+        // An unfinished game always leaves the ai something to play, none would stall it
+        let m = ai_move.ok_or_else(|| ChessError::NoAiMove(self.game_engine.current_player.clone()))?;
+        self.game_engine.try_play(m)?;
+        Ok(())
     }
 }
 
 
 impl Game for AiVsAi {
-    fn play(&mut self, m: Play) {
-        self.ai_play();
+    fn play(&mut self, m: Play) -> Result<(), ChessError> {
+        self.ai_play()
     }
 
-    fn promote(&mut self, p: Promote) {
-        todo!()
+    // The ais promote on their own
+    fn promote(&mut self, p: Promote) -> Result<(), ChessError> {
+        Err(IllegalMove::NoPromotionPending.into())
     }
 
     fn webapp_repr(&self) -> GameWebappRepr {
-        let board_repr = self.game_engine.to_webapp();
-        let board_history = self.game_engine.board_history.iter().map(|b| b.to_webapp()).collect();
-        GameWebappRepr {
-            current_player: self.game_engine.current_player.clone(),
-            turn: self.game_engine.turn,
-            board: board_repr,
-            board_history: board_history,
-        }
+        GameWebappRepr::from_engine(&self.game_engine)
+    }
+
+    fn to_pgn(&self) -> Result<String, ChessError> {
+        write_pgn(&self.game_engine, &self.white_ai.name(), &self.black_ai.name())
     }
 }
 
@@ -300,13 +664,393 @@ pub struct GameWebappRepr {
     current_player: Color,
     turn: usize,
     board: WebappRepr,
-    board_history: Vec<WebappRepr>
+    board_history: Vec<WebappRepr>,
+    outcome: Option<GameOutcome>,
+    // Checks given by white then black, for Three-check
+    checks: [u8; 2],
+    // Pieces white then black can drop, for Crazyhouse
+    pockets: Option<[Pocket; 2]>
+}
+
+impl GameWebappRepr {
+    // The whole game shown, no player to hide anything from
+    fn from_engine(engine: &GameEngine) -> Self {
+        Self {
+            current_player: engine.current_player.clone(),
+            turn: engine.turn,
+            board: engine.to_webapp(),
+            board_history: engine.board_history.iter().map(|b| b.to_webapp()).collect(),
+            outcome: engine.outcome.clone(),
+            checks: engine.board.checks,
+            pockets: engine.board.pockets,
+        }
+    }
 }
 
 pub trait Game {
-    fn play(&mut self, m: Play);
+    fn play(&mut self, m: Play) -> Result<(), ChessError>;
 
-    fn promote(&mut self, p: Promote);
+    fn promote(&mut self, p: Promote) -> Result<(), ChessError>;
 
     fn webapp_repr(&self) -> GameWebappRepr;
+
+    fn to_pgn(&self) -> Result<String, ChessError>;
+}
+
+
+// A move on one of the two Bughouse boards in UCI ("e2e4", "N@f7"), without one the ais to move play
+#[derive(Serialize, Deserialize, Message)]
+#[rtype(result="Result<BughouseWebappRepr, ChessError>")]
+pub struct BughousePlay {
+    pub board: usize,
+    #[serde(default)]
+    pub uci: Option<String>
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BughouseWebappRepr {
+    boards: Vec<GameWebappRepr>,
+    // Milliseconds left to white then black, on each board
+    clocks: Vec<[u64; 2]>,
+    // The board that decided the game and its result, shared by both boards
+    outcome: Option<(usize, GameOutcome)>
+}
+
+// Two boards played side by side: white on board 0 and black on board 1 are partners against the two others,
+// and the pieces a player takes go to the pocket of their partner
+pub struct BughouseGame {
+    pub engines: [GameEngine; 2],
+    pub clocks: [Clock; 2],
+    // The ai of each seat, by board then white first, humans having none
+    ais: [[Option<Box<dyn Ai>>; 2]; 2],
+    pub outcome: Option<(usize, GameOutcome)>
+}
+
+impl BughouseGame {
+    pub fn new(time: Duration, ais: [[Option<Box<dyn Ai>>; 2]; 2]) -> Self {
+        let now = Instant::now();
+        let mut clocks = [Clock::new(time), Clock::new(time)];
+        for clock in &mut clocks {
+            clock.start(Color::White, now);
+        }
+        Self {
+            engines: [GameEngine::with_variant(Arc::new(Bughouse)), GameEngine::with_variant(Arc::new(Bughouse))],
+            clocks: clocks,
+            ais: ais,
+            outcome: None
+        }
+    }
+
+    pub fn play(&mut self, p: BughousePlay) -> Result<(), ChessError> {
+        let now = Instant::now();
+        self.check_flags(now);
+        if let Some(uci) = p.uci {
+            let engine = self.engines.get(p.board).ok_or(ChessError::NoSuchBoard(p.board))?;
+            // The seats of the ais don't take the player input
+            if self.ais[p.board][bitboard::color_index(&engine.current_player)].is_some() {
+                return Err(IllegalMove::NotYourTurn(engine.current_player.clone()).into())
+            }
+            let m = Move::from_uci(&uci, engine)?;
+            self.play_at(p.board, m, now)?;
+        }
+        self.ai_play()
+    }
+
+    // Every ai to move plays once, the ais are not trusted and a rejected move is reported
+    pub fn ai_play(&mut self) -> Result<(), ChessError> {
+        for board in 0..2 {
+            if self.outcome.is_some() {
+                break
+            }
+            let seat = bitboard::color_index(&self.engines[board].current_player);
+            let m = match self.ais[board][seat].as_mut() {
+                Some(ai) => ai.play(&self.engines[board].ai_view()),
+                None => continue
+            };
+            if let Some(m) = m {
+                self.play_at(board, m, Instant::now())?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn play_at(&mut self, board: usize, m: Move, now: Instant) -> Result<(), ChessError> {
+        if board > 1 {
+            return Err(ChessError::NoSuchBoard(board))
+        }
+        self.check_flags(now);
+        if self.outcome.is_some() {
+            return Err(IllegalMove::GameOver.into())
+        }
+        let mover = self.engines[board].current_player.clone();
+        let pocket = |engine: &GameEngine| engine.board.pockets.unwrap_or_default()[bitboard::color_index(&mover)];
+        let before = pocket(&self.engines[board]);
+        self.engines[board].try_play(m)?;
+        self.clocks[board].press(now);
+        // The capture went to the pocket of the mover, it belongs to their partner
+        let after = pocket(&self.engines[board]);
+        for ptype in Pocket::PIECES.iter().filter(|p| after.count(p) > before.count(p)) {
+            self.pass_piece(board, &mover, ptype);
+        }
+        if let Some(outcome) = self.engines[board].outcome.clone() {
+            self.end(board, outcome, now);
+        }
+        Ok(())
+    }
+
+    // The partner of `color` plays the other color on the other board
+    fn pass_piece(&mut self, board: usize, color: &Color, ptype: &PieceType) {
+        let engine = &mut self.engines[board];
+        if let Some(pockets) = engine.board.pockets.as_mut() {
+            pockets[bitboard::color_index(color)].take(ptype);
+        }
+        engine.refresh_position();
+        let partner = &mut self.engines[1 - board];
+        if let Some(pockets) = partner.board.pockets.as_mut() {
+            pockets[bitboard::color_index(&color.other())].add(ptype);
+        }
+        partner.refresh_position();
+    }
+
+    // A player out of time loses for their team, even while the other board is being played
+    pub fn check_flags(&mut self, now: Instant) {
+        if self.outcome.is_some() {
+            return
+        }
+        for board in 0..2 {
+            if let Some(loser) = self.clocks[board].flagged(now) {
+                let outcome = GameOutcome::TimeOut { winner: loser.other() };
+                self.engines[board].outcome = Some(outcome.clone());
+                return self.end(board, outcome, now)
+            }
+        }
+    }
+
+    fn end(&mut self, board: usize, outcome: GameOutcome, now: Instant) {
+        for clock in &mut self.clocks {
+            clock.stop(now);
+        }
+        self.outcome = Some((board, outcome));
+    }
+
+    pub fn webapp_repr(&self) -> BughouseWebappRepr {
+        let now = Instant::now();
+        BughouseWebappRepr {
+            boards: self.engines.iter().map(GameWebappRepr::from_engine).collect(),
+            clocks: self.clocks
+                .iter()
+                .map(|clock| [Color::White, Color::Black].map(|c| clock.remaining(&c, now).as_millis() as u64))
+                .collect(),
+            outcome: self.outcome.clone()
+        }
+    }
+}
+
+
+#[test]
+fn test_game_outcome() {
+    let mate = GameEngine::from_fen("rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3").unwrap();
+    assert_eq!(mate.compute_outcome(), Some(GameOutcome::Checkmate { winner: Color::Black }));
+    let stalemate = GameEngine::from_fen("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1").unwrap();
+    assert_eq!(stalemate.compute_outcome(), Some(GameOutcome::Stalemate));
+    let bishops = GameEngine::from_fen("8/8/3bk3/8/8/2K1B3/8/8 w - - 0 1").unwrap();
+    assert_eq!(bishops.compute_outcome(), Some(GameOutcome::InsufficientMaterial));
+    let fifty = GameEngine::from_fen("8/8/3qk3/8/8/2KR4/8/8 w - - 100 80").unwrap();
+    assert_eq!(fifty.compute_outcome(), Some(GameOutcome::FiftyMoveRule));
+
+    // Knights shuffling back and forth repeat the starting position
+    let mut engine = GameEngine::from_fen(crate::fen::STARTING_FEN).unwrap();
+    for _ in 0..2 {
+        for m in [Move::Move((7, 6), (5, 5)), Move::Move((0, 6), (2, 5)), Move::Move((5, 5), (7, 6)), Move::Move((2, 5), (0, 6))] {
+            assert_eq!(engine.outcome, None);
+            engine.play_bypass(m).unwrap();
+            engine.finish_turn();
+            engine.prepare_new_turn();
+            engine.update_outcome();
+        }
+    }
+    assert_eq!(engine.outcome, Some(GameOutcome::ThreefoldRepetition));
+}
+
+#[test]
+fn test_make_unmake_move() {
+    let fens = [
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "r3k2r/1P6/8/3Pp3/8/2N3N1/8/R3K2R w KQkq e6 0 1",
+    ];
+    let snapshot = |engine: &GameEngine| {
+        let mut moves: Vec<_> = engine.gen_all_moves().iter().map(|m| format!("{:?}", m)).collect();
+        moves.sort();
+        (
+            engine.to_fen(),
+            engine.board.faction.white_controlled.clone(),
+            engine.board.faction.black_controlled.clone(),
+            engine.current_player.clone(),
+            engine.check,
+            engine.position_history.clone(),
+            moves
+        )
+    };
+    for fen in fens {
+        let mut engine = GameEngine::from_fen(fen).unwrap();
+        let before = snapshot(&engine);
+        for m in engine.gen_all_moves() {
+            let undo = engine.make_move(&m).unwrap();
+            for reply in engine.gen_all_moves() {
+                let reply_undo = engine.make_move(&reply).unwrap();
+                engine.unmake_move(reply_undo);
+            }
+            engine.unmake_move(undo);
+            assert_eq!(snapshot(&engine), before, "{:?}", m);
+        }
+        // A promotion is a single move, taken back at once
+        let promotion = Move::Promote((1, 1), (0, 0), CanPromoteTo::Queen);
+        if engine.gen_all_moves().contains(&promotion) {
+            let undo = engine.make_move(&promotion).unwrap();
+            assert!(engine.check);
+            engine.unmake_move(undo);
+            assert_eq!(snapshot(&engine), before);
+        }
+    }
+}
+
+
+#[test]
+fn test_try_play() {
+    let mut engine = GameEngine::from_fen("4k3/1P6/8/8/8/4r3/4B3/4K2R w K - 0 1").unwrap();
+    assert_eq!(engine.try_play(Move::Move((6, 4), (5, 3))), Err(IllegalMove::PiecePinned((6, 4)).into()));
+    assert_eq!(engine.try_play(Move::Move((4, 4), (3, 4))), Err(IllegalMove::NoPiece((4, 4)).into()));
+    assert_eq!(engine.try_play(Move::Move((0, 4), (0, 3))), Err(IllegalMove::WrongSideToMove(Color::White).into()));
+    assert_eq!(engine.try_play(Move::Move((7, 7), (3, 3))), Err(IllegalMove::InvalidMove(Move::Move((7, 7), (3, 3))).into()));
+    assert_eq!(engine.try_play(Move::Move((1, 1), (0, 1))), Err(IllegalMove::MissingPromotion((0, 1)).into()));
+    assert_eq!(engine.try_play(Move::Promote((1, 1), (0, 1), CanPromoteTo::Queen)), Ok(MoveOutcome::Played { check: true }));
+    assert_eq!(engine.board.board[0][1].get_type(), Some(PieceType::Queen));
+    assert_eq!(engine.try_play(Move::Move((5, 4), (4, 4))), Err(IllegalMove::KingInCheck.into()));
+    assert_eq!(engine.current_player, Color::Black);
+
+    // Unchecked plays report bad squares instead of panicking, leaving the board untouched
+    let before = engine.board.to_fen();
+    assert_eq!(engine.play_bypass(Move::Move((4, 4), (3, 4))), Err(ChessError::EmptySquare((4, 4))));
+    assert_eq!(engine.play_bypass(Move::Move((0, 4), (8, 4))), Err(ChessError::OutOfBoard((8, 4))));
+    assert_eq!(engine.board.to_fen(), before);
+    assert_eq!(ChessBoard::new_empty().locate_king(&Color::White), Err(ChessError::NoKing(Color::White)));
+}
+
+#[test]
+fn test_illegal_ai_move() {
+    // Always tries to move from an empty square
+    struct BrokenAi;
+    impl Ai for BrokenAi {
+        fn play(&mut self, _engine: &GameEngine) -> Option<Move> {
+            Some(Move::Move((4, 4), (3, 4)))
+        }
+
+        fn eval_position(&self, _board: &ChessBoard) -> f64 {
+            0.
+        }
+
+        fn new(_machine_player: Color) -> Self {
+            Self
+        }
+
+        fn name(&self) -> String {
+            "BrokenAi".into()
+        }
+    }
+    let no_piece = ChessError::from(IllegalMove::NoPiece((4, 4)));
+    assert_eq!(PlayerVsIa::new(Color::Black, Box::new(BrokenAi)).err(), Some(no_piece.clone()));
+    let mut game = AiVsAi::new(Box::new(BrokenAi), Box::new(BrokenAi));
+    assert_eq!(game.ai_play(), Err(no_piece));
+    assert_eq!((game.game_engine.turn, game.game_engine.current_player.clone()), (0, Color::White));
+
+    // Never finds a move, which would leave the game waiting forever
+    struct SilentAi;
+    impl Ai for SilentAi {
+        fn play(&mut self, _engine: &GameEngine) -> Option<Move> {
+            None
+        }
+
+        fn eval_position(&self, _board: &ChessBoard) -> f64 {
+            0.
+        }
+
+        fn new(_machine_player: Color) -> Self {
+            Self
+        }
+
+        fn name(&self) -> String {
+            "SilentAi".into()
+        }
+    }
+    let mut game = AiVsAi::new(Box::new(SilentAi), Box::new(SilentAi));
+    assert_eq!(game.ai_play(), Err(ChessError::NoAiMove(Color::White)));
+    let mut game = PlayerVsIa::new(Color::White, Box::new(SilentAi)).unwrap();
+    assert_eq!(game.player_play(Move::Move((6, 4), (4, 4))), Err(ChessError::NoAiMove(Color::Black)));
+}
+
+#[test]
+fn test_rollback() {
+    let mut engine = GameEngine::new();
+    assert_eq!(engine.rollback(), None);
+    let start = engine.board.position_hash();
+    let knights = [Move::Move((7, 6), (5, 5)), Move::Move((0, 6), (2, 5)), Move::Move((5, 5), (7, 6)), Move::Move((2, 5), (0, 6))];
+    for m in knights.clone() {
+        engine.try_play(m).unwrap();
+    }
+    assert_eq!(engine.rollback(), Some(knights[3].clone()));
+    assert_eq!(engine.rollback(), Some(knights[2].clone()));
+    assert_eq!((engine.turn, engine.current_player.clone(), engine.position_history.len()), (2, Color::White, 3));
+    // Taken back positions don't count towards a repetition
+    for m in knights[2..].iter().chain(&knights[..2]) {
+        engine.try_play(m.clone()).unwrap();
+    }
+    assert_eq!(engine.outcome, None);
+    while engine.rollback().is_some() {}
+    assert_eq!((engine.turn, engine.board.position_hash(), engine.position_history.clone()), (0, start, vec![start]));
+}
+
+#[test]
+fn test_bughouse() {
+    use crate::zobrist::Zobrist;
+    let start = Instant::now();
+    let at = |secs: u64| start + Duration::from_secs(secs);
+    let mut game = BughouseGame::new(Duration::from_secs(60), [[None, None], [None, None]]);
+    for clock in &mut game.clocks {
+        clock.start(Color::White, start);
+    }
+
+    // The pawn white takes on board 0 goes to its partner, black on board 1
+    for (i, m) in [Move::Move((6, 4), (4, 4)), Move::Move((1, 3), (3, 3)), Move::Take((4, 4), (3, 3))].into_iter().enumerate() {
+        game.play_at(0, m, at(i as u64 + 1)).unwrap();
+    }
+    assert_eq!(game.engines[0].board.pockets, Some([Pocket::default(); 2]));
+    assert_eq!(game.engines[1].board.pockets.unwrap()[1].count(&PieceType::Pawn), 1);
+    for engine in &game.engines {
+        assert_eq!(engine.board.position_hash(), Zobrist::keys().hash(&engine.board));
+    }
+    game.play(BughousePlay { board: 1, uci: Some("e2e4".into()) }).unwrap();
+    game.play(BughousePlay { board: 1, uci: Some("P@e5".into()) }).unwrap();
+    assert_eq!(game.engines[1].board.pockets, Some([Pocket::default(); 2]));
+    assert!(game.play(BughousePlay { board: 2, uci: Some("e2e4".into()) }).is_err());
+
+    // Black on board 0 has been thinking since its third second, its flag falls and its team loses on both boards
+    assert_eq!(game.clocks[0].remaining(&Color::White, at(30)), Duration::from_secs(58));
+    let outcome = GameOutcome::TimeOut { winner: Color::White };
+    assert!(game.play_at(0, Move::Move((1, 4), (2, 4)), at(63)).is_err());
+    assert_eq!(game.outcome, Some((0, outcome)));
+    assert!(game.play_at(1, Move::Move((6, 3), (4, 3)), at(63)).is_err());
+
+    // An ai seat plays on its own, opening and then answering the human
+    let ai: Box<dyn Ai> = Box::new(crate::ai::DummyRandomIA::new(Color::White));
+    let mut game = BughouseGame::new(Duration::from_secs(60), [[Some(ai), None], [None, None]]);
+    assert_eq!(game.ai_play(), Ok(()));
+    assert_eq!(game.engines[0].turn, 1);
+    game.play(BughousePlay { board: 0, uci: Some("e7e5".into()) }).unwrap();
+    assert_eq!(game.engines[0].turn, 3);
+    // Nobody moves for the ai, the board is left as it was
+    let ai: Box<dyn Ai> = Box::new(crate::ai::DummyRandomIA::new(Color::White));
+    let mut game = BughouseGame::new(Duration::from_secs(60), [[Some(ai), None], [None, None]]);
+    let refused = game.play(BughousePlay { board: 0, uci: Some("d2d4".into()) });
+    assert_eq!(refused, Err(IllegalMove::NotYourTurn(Color::White).into()));
+    assert_eq!(game.engines[0].turn, 0);
 }
@@ -3,4 +3,14 @@ pub mod piece;
 pub mod server;
 pub mod game;
 pub mod ai;
-pub mod zobrist;
\ No newline at end of file
+pub mod zobrist;
+pub mod fen;
+pub mod pgn;
+pub mod san;
+pub mod uci;
+pub mod bitboard;
+pub mod perft;
+pub mod error;
+pub mod validate;
+pub mod variant;
+pub mod clock;
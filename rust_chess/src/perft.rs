@@ -0,0 +1,103 @@
+use rayon::prelude::*;
+
+use crate::{
+    chessbord::ChessBoard,
+    error::ChessError,
+    game::GameEngine,
+};
+
+fn perft_board(board: &mut ChessBoard, depth: usize) -> Result<u64, ChessError> {
+    if depth == 0 {
+        return Ok(1)
+    }
+    let moves = board.gen_all_moves(&board.side_to_move);
+    // Bulk counting, no need to play the leaves
+    if depth == 1 {
+        return Ok(moves.len() as u64)
+    }
+    let mut nodes = 0;
+    for m in moves {
+        let undo = board.make_move(m)?;
+        let below = perft_board(board, depth - 1);
+        board.unmake_move(undo);
+        nodes += below?;
+    }
+    Ok(nodes)
+}
+
+impl GameEngine {
+    // Number of leaf positions reachable in `depth` plies, to check the move generator against known counts
+    pub fn perft(&self, depth: usize) -> Result<u64, ChessError> {
+        let mut board = self.board.clone();
+        perft_board(&mut board, depth)
+    }
+
+    // Same as `perft`, each root move being searched on its own thread
+    pub fn perft_parallel(&self, depth: usize) -> Result<u64, ChessError> {
+        if depth <= 1 {
+            return self.perft(depth)
+        }
+        self.board.gen_all_moves(&self.board.side_to_move)
+            .into_par_iter()
+            .map_init(|| self.board.clone(), |board, m| {
+                let undo = board.make_move(m)?;
+                let nodes = perft_board(board, depth - 1);
+                board.unmake_move(undo);
+                nodes
+            })
+            .sum()
+    }
+
+    // The perft count below each root move, keyed by its UCI notation and sorted like most engines print it
+    pub fn divide(&self, depth: usize) -> Result<Vec<(String, u64)>, ChessError> {
+        let mut board = self.board.clone();
+        let mut counts = board.gen_all_moves(&board.side_to_move)
+            .into_iter()
+            .map(|m| {
+                let uci = m.to_uci(&board);
+                let undo = board.make_move(m)?;
+                let nodes = perft_board(&mut board, depth.saturating_sub(1));
+                board.unmake_move(undo);
+                Ok((uci, nodes?))
+            })
+            .collect::<Result<Vec<_>, ChessError>>()?;
+        counts.sort();
+        Ok(counts)
+    }
+}
+
+
+#[test]
+fn test_perft() {
+    // Reference positions and counts from the Chess Programming Wiki, depths kept low for debug builds
+    let positions = [
+        (crate::fen::STARTING_FEN, 3, 8902),
+        ("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1", 3, 97862),
+        ("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1", 4, 43238),
+        ("r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1", 3, 9467),
+        ("rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8", 3, 62379),
+        ("r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10", 3, 89890),
+    ];
+    for (fen, depth, nodes) in positions {
+        let engine = GameEngine::from_fen(fen).unwrap();
+        assert_eq!(engine.perft_parallel(depth), Ok(nodes), "{}", fen);
+    }
+    // Chess960 positions, castling rooks named by their file
+    let positions = [
+        ("bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9", 3, 12189),
+        ("2nnrbkr/p1qppppp/8/1ppb4/6PP/3PP3/PPP2P2/BQNNRBKR w HEhe - 1 9", 3, 18002),
+        ("b1q1rrkb/pppppppp/3nn3/8/P7/1PPP4/4PPPP/BQNNRKRB w GE - 1 9", 3, 10471),
+    ];
+    for (fen, depth, nodes) in positions {
+        let engine = GameEngine::from_fen(fen).unwrap();
+        assert_eq!(engine.perft_parallel(depth), Ok(nodes), "{}", fen);
+    }
+    // The default board is the orthodox starting position
+    assert_eq!(GameEngine::new().perft(3), Ok(8902));
+    let engine = GameEngine::from_fen(crate::fen::STARTING_FEN).unwrap();
+    assert_eq!(engine.perft(2), Ok(400));
+    let divide = engine.divide(2).unwrap();
+    assert_eq!(divide.len(), 20);
+    assert_eq!(divide[0], ("a2a3".to_string(), 20));
+    assert_eq!(divide.iter().map(|(_, n)| n).sum::<u64>(), 400);
+}
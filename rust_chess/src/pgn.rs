@@ -0,0 +1,432 @@
+use std::fmt;
+
+use crate::{
+    chessbord::ChessBoard,
+    error::ChessError,
+    fen::{FenError, STARTING_FEN},
+    game::{GameEngine, GameOutcome},
+    piece::{Color, Move},
+    variant::VariantKind,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PgnError {
+    InvalidTag(String),
+    UnterminatedComment,
+    UnbalancedVariation,
+    IllegalMove { ply: usize, san: String },
+    Fen(FenError),
+}
+
+impl fmt::Display for PgnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidTag(tag) => write!(f, "invalid tag pair: {}", tag),
+            Self::UnterminatedComment => write!(f, "unterminated comment"),
+            Self::UnbalancedVariation => write!(f, "unbalanced variation parentheses"),
+            Self::IllegalMove { ply, san } => write!(f, "illegal move at ply {}: {}", ply, san),
+            Self::Fen(e) => write!(f, "invalid FEN tag: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for PgnError {}
+
+impl From<FenError> for PgnError {
+    fn from(e: FenError) -> Self {
+        Self::Fen(e)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PgnResult {
+    WhiteWins,
+    BlackWins,
+    Draw,
+    Unknown,
+}
+
+impl PgnResult {
+    fn parse(token: &str) -> Option<Self> {
+        match token {
+            "1-0" => Some(Self::WhiteWins),
+            "0-1" => Some(Self::BlackWins),
+            "1/2-1/2" => Some(Self::Draw),
+            "*" => Some(Self::Unknown),
+            _ => None
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::WhiteWins => "1-0",
+            Self::BlackWins => "0-1",
+            Self::Draw => "1/2-1/2",
+            Self::Unknown => "*",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PgnGame {
+    pub tags: Vec<(String, String)>,
+    // One entry per ply, in the same layout as `GameEngine::move_history`
+    pub moves: Vec<Move>,
+    pub result: PgnResult,
+}
+
+impl PgnGame {
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+
+    pub fn initial_engine(&self) -> Result<GameEngine, PgnError> {
+        let variant = self.tag("Variant")
+            .and_then(VariantKind::from_name)
+            .unwrap_or(VariantKind::Standard)
+            .instantiate();
+        let mut board = match self.tag("FEN") {
+            Some(fen) => {
+                let board = ChessBoard::from_fen(fen)?;
+                board.validate().map_err(FenError::IllegalPosition)?;
+                board
+            },
+            None => variant.starting_board()
+        };
+        if self.tag("Variant").is_some_and(|v| v.eq_ignore_ascii_case("chess960")) {
+            board.chess960 = true;
+        }
+        Ok(GameEngine::from_board_and_variant(board, variant))
+    }
+
+    pub fn replay(&self) -> Result<GameEngine, ChessError> {
+        let mut engine = self.initial_engine()?;
+        for m in &self.moves {
+            play_ply(&mut engine, m.clone())?;
+        }
+        Ok(engine)
+    }
+}
+
+fn play_ply(engine: &mut GameEngine, m: Move) -> Result<(), ChessError> {
+    engine.play_bypass(m)?;
+    engine.finish_turn();
+    engine.prepare_new_turn();
+    Ok(())
+}
+
+struct PgnReader {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl PgnReader {
+    fn new(input: &str) -> Self {
+        Self { chars: input.chars().collect(), pos: 0 }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).cloned()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(c) if c.is_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    fn skip_line(&mut self) {
+        while let Some(c) = self.peek() {
+            self.pos += 1;
+            if c == '\n' {
+                break
+            }
+        }
+    }
+
+    fn at_line_start(&self) -> bool {
+        self.pos == 0 || self.chars[self.pos - 1] == '\n'
+    }
+
+    fn read_tags(&mut self) -> Result<Vec<(String, String)>, PgnError> {
+        let mut tags = vec!();
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                Some('[') => tags.push(self.read_tag()?),
+                Some('%') if self.at_line_start() => self.skip_line(),
+                _ => return Ok(tags)
+            }
+        }
+    }
+
+    fn read_tag(&mut self) -> Result<(String, String), PgnError> {
+        let start = self.pos;
+        let err = |reader: &Self| {
+            let end = reader.chars[start..].iter().position(|c| *c == '\n').map_or(reader.chars.len(), |i| start + i);
+            PgnError::InvalidTag(reader.chars[start..end].iter().collect())
+        };
+        // Skipping the opening bracket
+        self.pos += 1;
+        self.skip_whitespace();
+        let mut name = String::new();
+        while let Some(c) = self.peek() {
+            if !(c.is_alphanumeric() || c == '_') {
+                break
+            }
+            name.push(c);
+            self.pos += 1;
+        }
+        self.skip_whitespace();
+        if name.is_empty() || self.peek() != Some('"') {
+            return Err(err(self))
+        }
+        self.pos += 1;
+        let mut value = String::new();
+        loop {
+            match self.peek() {
+                Some('\\') => {
+                    self.pos += 1;
+                    match self.peek() {
+                        Some(c) => value.push(c),
+                        None => return Err(err(self))
+                    }
+                },
+                Some('"') => break,
+                Some(c) => value.push(c),
+                None => return Err(err(self))
+            }
+            self.pos += 1;
+        }
+        self.pos += 1;
+        self.skip_whitespace();
+        if self.peek() != Some(']') {
+            return Err(err(self))
+        }
+        self.pos += 1;
+        Ok((name, value))
+    }
+
+    // Reads the movetext up to the game termination marker, variations are skipped
+    fn read_movetext(&mut self, engine: &mut GameEngine) -> Result<(Vec<Move>, PgnResult), PgnError> {
+        let mut moves = vec!();
+        let mut depth = 0;
+        loop {
+            self.skip_whitespace();
+            let c = match self.peek() {
+                Some(c) => c,
+                None if depth > 0 => return Err(PgnError::UnbalancedVariation),
+                None => return Ok((moves, PgnResult::Unknown))
+            };
+            match c {
+                '{' => {
+                    match self.chars[self.pos..].iter().position(|c| *c == '}') {
+                        Some(i) => self.pos += i + 1,
+                        None => return Err(PgnError::UnterminatedComment)
+                    }
+                },
+                ';' => self.skip_line(),
+                '%' if self.at_line_start() => self.skip_line(),
+                '(' => {
+                    depth += 1;
+                    self.pos += 1;
+                },
+                ')' => {
+                    if depth == 0 {
+                        return Err(PgnError::UnbalancedVariation)
+                    }
+                    depth -= 1;
+                    self.pos += 1;
+                },
+                // A new tag section without termination marker starts the next game
+                '[' if depth == 0 => return Ok((moves, PgnResult::Unknown)),
+                _ => {
+                    let token = self.read_token();
+                    if let Some(result) = PgnResult::parse(&token) {
+                        if depth == 0 {
+                            return Ok((moves, result))
+                        }
+                        continue
+                    }
+                    if depth > 0 {
+                        continue
+                    }
+                    // Move numbers may be glued to the move ("1.e4")
+                    let san = token.trim_start_matches(|c: char| c.is_ascii_digit() || c == '.');
+                    if san.is_empty() || san.starts_with('$') {
+                        continue
+                    }
+                    let illegal = || PgnError::IllegalMove { ply: moves.len() + 1, san: san.to_string() };
+                    let m = Move::from_san(san, engine).map_err(|_| illegal())?;
+                    play_ply(engine, m.clone()).map_err(|_| illegal())?;
+                    moves.push(m);
+                }
+            }
+        }
+    }
+
+    fn read_token(&mut self) -> String {
+        let mut token = String::new();
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() || "{}();[".contains(c) || (c == '$' && !token.is_empty()) {
+                break
+            }
+            token.push(c);
+            self.pos += 1;
+        }
+        if token.is_empty() {
+            // An unknown delimiter, we skip it so the reader always progresses
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn is_exhausted(&mut self) -> bool {
+        self.skip_whitespace();
+        self.pos >= self.chars.len()
+    }
+}
+
+// Parses every game of a PGN file, replaying the main line through the engine
+pub fn parse_pgn(input: &str) -> Result<Vec<PgnGame>, PgnError> {
+    let mut reader = PgnReader::new(input);
+    let mut games = vec!();
+    while !reader.is_exhausted() {
+        let tags = reader.read_tags()?;
+        let mut game = PgnGame { tags, moves: vec!(), result: PgnResult::Unknown };
+        let mut engine = game.initial_engine()?;
+        let (moves, result) = reader.read_movetext(&mut engine)?;
+        game.moves = moves;
+        game.result = result;
+        games.push(game);
+    }
+    Ok(games)
+}
+
+impl From<Option<&GameOutcome>> for PgnResult {
+    fn from(outcome: Option<&GameOutcome>) -> Self {
+        match outcome.map(|o| o.winner()) {
+            None => Self::Unknown,
+            Some(Some(Color::White)) => Self::WhiteWins,
+            Some(Some(Color::Black)) => Self::BlackWins,
+            Some(None) => Self::Draw
+        }
+    }
+}
+
+fn escape_tag(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// Writes the game played so far by the engine with the seven tag roster
+pub fn write_pgn(engine: &GameEngine, white: &str, black: &str) -> Result<String, ChessError> {
+    let start_board = engine.board_history.first().unwrap_or(&engine.board);
+    let start_fen = start_board.to_fen();
+    // The moves are replayed under the game's rules, drops and explosions included
+    let mut replay = GameEngine::from_board_and_variant(start_board.clone(), engine.variant.clone());
+    let result = PgnResult::from(engine.compute_outcome().as_ref());
+
+    let mut pgn = String::new();
+    let mut tags = vec![
+        ("Event", "rust-chess game".to_string()),
+        ("Site", "?".to_string()),
+        ("Date", "????.??.??".to_string()),
+        ("Round", "-".to_string()),
+        ("White", white.to_string()),
+        ("Black", black.to_string()),
+        ("Result", result.as_str().to_string()),
+    ];
+    match engine.variant.name() {
+        "Standard" if start_board.chess960 => tags.push(("Variant", "Chess960".to_string())),
+        "Standard" => {},
+        name => tags.push(("Variant", name.to_string()))
+    }
+    if start_fen != STARTING_FEN || start_board.chess960 {
+        tags.push(("SetUp", "1".to_string()));
+        tags.push(("FEN", start_fen));
+    }
+    for (name, value) in tags {
+        pgn.push_str(&format!("[{} \"{}\"]\n", name, escape_tag(&value)));
+    }
+    pgn.push('\n');
+
+    let mut tokens = vec!();
+    for (i, m) in engine.move_history.iter().enumerate() {
+        let fullmove = replay.board.fullmove_number;
+        match replay.current_player {
+            Color::White => tokens.push(format!("{}.", fullmove)),
+            Color::Black if i == 0 => tokens.push(format!("{}...", fullmove)),
+            Color::Black => {}
+        }
+        tokens.push(m.to_san(&replay)?);
+        play_ply(&mut replay, m.clone())?;
+    }
+    tokens.push(result.as_str().to_string());
+
+    // Export format keeps lines under 80 characters
+    let mut line = String::new();
+    for token in tokens {
+        if !line.is_empty() && line.len() + token.len() + 1 > 79 {
+            pgn.push_str(&line);
+            pgn.push('\n');
+            line.clear();
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(&token);
+    }
+    pgn.push_str(&line);
+    pgn.push('\n');
+    Ok(pgn)
+}
+
+
+#[test]
+fn test_pgn_round_trip() {
+    let input = r#"[Event "Casual game"]
+[White "Alice"]
+[Black "Bob"]
+[Result "1-0"]
+
+1. e4 e5 2. Nf3 {Developing} Nc6 3. Bc4 (3. Bb5 a6 (3... Nf6) 4. Ba4) 3... Bc5 $1
+4. O-O d6?! 5. c3 Nf6 6. d4 exd4 7. cxd4 Bb6 8. Nc3 O-O 9. e5 dxe5 10. dxe5 Nxe5
+11. Nxe5 Qxd1 12. Rxd1 1-0
+
+[Event "Fool's mate"]
+
+1.f3 e5 2.g4 Qh4# 0-1
+"#;
+    let games = parse_pgn(input).unwrap();
+    assert_eq!(games.len(), 2);
+    assert_eq!(games[0].tag("White"), Some("Alice"));
+    assert_eq!(games[0].moves.len(), 23);
+    assert_eq!(games[0].result, PgnResult::WhiteWins);
+    assert_eq!(games[1].moves.len(), 4);
+
+    let engine = games[1].replay().unwrap();
+    assert!(engine.check);
+    let pgn = write_pgn(&engine, "White", "Black").unwrap();
+    assert!(pgn.contains("[Result \"0-1\"]"));
+    assert!(pgn.ends_with("1. f3 e5 2. g4 Qh4# 0-1\n"));
+    let reparsed = parse_pgn(&pgn).unwrap();
+    assert_eq!(reparsed[0].moves, games[1].moves);
+
+    assert!(matches!(parse_pgn("1. e4 e5 2. Ke3"), Err(PgnError::IllegalMove { ply: 3, .. })));
+}
+
+#[test]
+fn test_pgn_variant_round_trip() {
+    let input = "[Variant \"Crazyhouse\"]\n\n1. e4 d5 2. exd5 Qxd5 3. Nc3 Qa5 4. P@b4 Qxb4 *\n";
+    let games = parse_pgn(input).unwrap();
+    assert_eq!(games[0].moves[6], Move::Drop(Color::White, crate::piece::PieceType::Pawn, (4, 1)));
+
+    let engine = games[0].replay().unwrap();
+    assert_eq!(engine.variant.name(), "Crazyhouse");
+    let pgn = write_pgn(&engine, "White", "Black").unwrap();
+    assert!(pgn.contains("[Variant \"Crazyhouse\"]"));
+    assert!(pgn.ends_with("1. e4 d5 2. exd5 Qxd5 3. Nc3 Qa5 4. P@b4 Qxb4 *\n"));
+    let reparsed = parse_pgn(&pgn).unwrap();
+    assert_eq!(reparsed[0].moves, games[0].moves);
+    assert_eq!(reparsed[0].replay().unwrap().to_fen(), engine.to_fen());
+}
@@ -1,8 +1,7 @@
-use std::collections::{HashMap, HashSet};
-
 use serde::{Serialize, Deserialize};
 
 use crate::chessbord::Faction;
+use crate::error::ChessError;
 
 use super::chessbord::{
     ChessBoard,
@@ -37,9 +36,14 @@ pub enum CanPromoteTo {
     Rook,
     Bishop,
     Knight,
-    Queen
+    Queen,
+    // Only in Antichess
+    King
 }
 
+// Every piece a pawn can promote to in orthodox chess, strongest first
+pub const PROMOTIONS: [CanPromoteTo; 4] = [CanPromoteTo::Queen, CanPromoteTo::Rook, CanPromoteTo::Bishop, CanPromoteTo::Knight];
+
 impl CanPromoteTo {
     pub fn get_zobrist_id(&self) -> u64 {
         match self {
@@ -47,6 +51,7 @@ impl CanPromoteTo {
             Self::Rook => 2,
             Self::Knight => 1,
             Self::Queen => 3,
+            Self::King => 4,
         }
     }
 }
@@ -58,6 +63,7 @@ impl Into<PieceType> for CanPromoteTo {
             Self::Bishop => PieceType::Bishop,
             Self::Knight => PieceType::Knight,
             Self::Queen => PieceType::Queen,
+            Self::King => PieceType::King,
         }
     }
 }
@@ -75,15 +81,16 @@ pub enum PieceType {
 }
 
 impl PieceType {
-    pub fn get_zobrist_id(&self) -> u64 {
+    pub fn get_zobrist_id(&self) -> Option<u64> {
         match self {
-            &Self::Pawn => 0,
-            &Self::Knight => 1,
-            &Self::Rook => 2,
-            &Self::Queen => 3,
-            &Self::King => 4,
-            &Self::Bishop => 5,
-            &Self::Empty => panic!("Zobrist should never be called on empty square")
+            &Self::Pawn => Some(0),
+            &Self::Knight => Some(1),
+            &Self::Rook => Some(2),
+            &Self::Queen => Some(3),
+            &Self::King => Some(4),
+            &Self::Bishop => Some(5),
+            // Empty squares are not hashed
+            &Self::Empty => None
         }
     }
 }
@@ -146,12 +153,13 @@ impl Piece {
         }
     }
 
-    pub fn get_zobrist_id(&self) -> u64 {
-        let mut zobrist_id = unsafe { self.get_type().unwrap_unchecked().get_zobrist_id() };
-        if unsafe { self.color().unwrap_unchecked() }== Color::Black {
+    pub fn get_zobrist_id(&self, pos: &Position) -> Result<u64, ChessError> {
+        let err = || ChessError::EmptySquare(*pos);
+        let mut zobrist_id = self.get_type().and_then(|t| t.get_zobrist_id()).ok_or_else(err)?;
+        if self.color().ok_or_else(err)? == Color::Black {
             zobrist_id += 6;
         }
-        zobrist_id
+        Ok(zobrist_id)
     }
 
     pub fn color(&self) -> Option<Color> {
@@ -188,86 +196,6 @@ impl Piece {
         }
     }
 
-    // We lose a bit of efficiency since we generate each moves twice, but it makes code tighter and simpler
-    pub fn get_controlled_squares(&self, board: &ChessBoard) -> Vec<Position> {
-        let mut controlled = vec!();
-        match self.get_type() {
-            Some(PieceType::Pawn) => {
-                let (x, y) = self.position().unwrap();
-                match self.color().unwrap() {
-                    Color::Black => {
-                        controlled.push((x + 1, y + 1));
-                        controlled.push((x + 1, y - 1));
-                    },
-                    Color::White => {
-                        controlled.push((x - 1, y + 1));
-                        controlled.push((x - 1, y - 1));
-                    }
-                }
-            },
-            _ => {
-                for m in self.gen_moves(board) {
-                    if let Move::Move(_, to) = m {
-                        controlled.push(to)
-                    }
-                    if let Move::Defend(p) = m {
-                        controlled.push(p)
-                    }
-                }
-            }
-        }
-        controlled
-    }
-
-    pub fn gen_moves_check(&self, board: &ChessBoard, check: bool, attack_vectors: &Vec<HashSet<Position>>) -> Vec<Move> {
-        if !check {
-            return self.gen_moves(board)
-        }
-
-        match self {
-            Piece::King(k) => k.gen_moves(board),
-            Piece::Empty => vec!(),
-            other @ _ if attack_vectors.len() == 1 => {
-                other.gen_moves(board)
-                    .into_iter()
-                    .filter(|m| {
-                        match m {
-                            Move::Move(_, to) => {
-                                attack_vectors.iter().find(|hm| hm.contains(to)).is_some()
-                            },
-                            Move::Take(_, to) => {
-                                attack_vectors.iter().find(|hm| hm.contains(to)).is_some()
-                            },
-                            _ => false
-                        }
-                    })
-                    .collect()
-            },
-            _ => vec!()
-        }
-    }
-
-    pub fn gen_moves(&self, board: &ChessBoard) -> Vec<Move> {
-        match self {
-            Piece::Pawn(p) => p.gen_moves(board),
-            Piece::Knight(p) => p.gen_moves(board),
-            Piece::Rook(p) => p.gen_moves(board),
-            Piece::Queen(p) => p.gen_moves(board),
-            Piece::King(p) => p.gen_moves(board),
-            Piece::Bishop(p) => p.gen_moves(board),
-            Piece::Empty => vec!(),
-        }
-    }
-
-    pub fn can_attack(&self, direction: &VectorDirection) -> bool {
-        match self {
-            Piece::Rook(p) => direction.is_line(),
-            Piece::Queen(p) => direction.is_line() || direction.is_diagonal(),
-            Piece::Bishop(p) => direction.is_diagonal(),
-            _ => false
-        }
-    }
-
     pub fn set_position(&mut self, pos: Position) {
         match self {
             Piece::Pawn(p) => {
@@ -289,23 +217,21 @@ impl Piece {
         }
     }
 
-    pub fn set_attack_vector(&mut self, direction: Option<VectorDirection>) {
+    pub fn has_moved(&self) -> bool {
         match self {
-            Piece::Pawn(p) => {
-                p.pin_vector = direction;
-            },
-            Piece::Knight(p) => {
-                p.pin_vector = direction;
-            },
-            Piece::Rook(p) => {
-                p.pin_vector = direction;
-            },
-            Piece::Queen(p) => {
-                p.pin_vector = direction;
-            },
-            Piece::Bishop(p) => {
-                p.pin_vector = direction;
-            },
+            Piece::Pawn(p) => p.has_moved,
+            Piece::Rook(p) => p.has_moved,
+            Piece::King(p) => p.has_moved,
+            _ => false
+        }
+    }
+
+    // Only meaningful for pieces whose first move matters (pawn double push, castling)
+    pub fn set_has_moved(&mut self, has_moved: bool) {
+        match self {
+            Piece::Pawn(p) => p.has_moved = has_moved,
+            Piece::Rook(p) => p.has_moved = has_moved,
+            Piece::King(p) => p.has_moved = has_moved,
             _ => {}
         }
     }
@@ -321,6 +247,7 @@ impl Piece {
                 },
                 threatened: false,
                 controll: None,
+                hidden: false,
             },
             Piece::Queen(p) => CellRepr {
                 piece: PieceRepr {
@@ -330,6 +257,7 @@ impl Piece {
                 },
                 threatened: false,
                 controll: None,
+                hidden: false,
             },
             Piece::Rook(p) => CellRepr {
                 piece: PieceRepr {
@@ -339,6 +267,7 @@ impl Piece {
                 },
                 threatened: false,
                 controll: None,
+                hidden: false,
             },
             Piece::Bishop(p) => CellRepr {
                 piece: PieceRepr {
@@ -348,6 +277,7 @@ impl Piece {
                 },
                 threatened: false,
                 controll: None,
+                hidden: false,
             },
             Piece::Knight(p) => CellRepr {
                 piece: PieceRepr {
@@ -357,6 +287,7 @@ impl Piece {
                 },
                 threatened: false,
                 controll: None,
+                hidden: false,
             },
             Piece::Pawn(p) => CellRepr {
                 piece: PieceRepr {
@@ -366,6 +297,7 @@ impl Piece {
                 },
                 threatened: false,
                 controll: None,
+                hidden: false,
             },
             Piece::Empty => CellRepr {
                 piece: PieceRepr {
@@ -375,6 +307,7 @@ impl Piece {
                 },
                 threatened: false,
                 controll: None,
+                hidden: false,
             },
         }
     }
@@ -382,65 +315,45 @@ impl Piece {
 
 pub type Position = (i8, i8);
 
+// Row 0 is black's back rank (rank 8) and column 0 is the a-file
+pub fn square_name(pos: &Position) -> String {
+    let file = (b'a' + pos.1 as u8) as char;
+    let rank = (b'8' - pos.0 as u8) as char;
+    format!("{}{}", file, rank)
+}
+
+pub fn parse_square(name: &str) -> Option<Position> {
+    let bytes = name.as_bytes();
+    if bytes.len() != 2 {
+        return None
+    }
+    let (file, rank) = (bytes[0], bytes[1]);
+    if !(b'a'..=b'h').contains(&file) || !(b'1'..=b'8').contains(&rank) {
+        return None
+    }
+    Some(((b'8' - rank) as i8, (file - b'a') as i8))
+}
+
 
 //// MOVES 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Move {
     Move(Position, Position),
     Take(Position, Position),
     EnPassant(Position, Position),
     KingsideCastle(Color),
     QueensideCastle(Color),
-    Promote(Position, CanPromoteTo),
-    Defend(Position),
-    Invalid
+    // A pawn moving or taking onto its last row, the promotion piece being part of the move
+    Promote(Position, Position, CanPromoteTo),
+    // A piece from the pocket put on an empty square, in Crazyhouse
+    Drop(Color, PieceType, Position),
 }
 
 impl Move {
-    // This function should be called only for move or take moves. Castling and en passant would make this function pannic
-    pub fn new(p: &impl PieceCommon, board: &ChessBoard, to: Position) -> Self {
-        if to.0 < 0 || to.0 > 7 || to.1 < 0 || to.1 > 7 {
-            return Move::Invalid
-        }
-        let dst = &board.board[to.0 as usize][to.1 as usize];
-
-        if !dst.is_empty() && (dst.color().unwrap() == p.color()) {
-            return Move::Defend(to)
-        }
-        // Otherwise we either move or take
-        match dst {
-            Piece::Empty => Self::Move(p.position(), to),
-            // We allow to pass through enemy king for controlled squares
-            Piece::King(k) if k.color != p.color() => Self::Move(p.position(), to),
-            p_ => Move::Take(p.position(), to),
-        }
-    }
-
-    pub fn new_with_enpassant(p: &impl PieceCommon, board: &ChessBoard, to: Position) -> Self {
-        let m = Self::new(p, board, to);
-        // If the move is a valid take, we return the move
-        match m {
-            Move::Take(_, _) => return m,
-            _ => {}
-        }
-        // Otherwise we check for enpassant
-        let to_enpassant = (p.position().0, to.1);
-        match Self::new(p, board, to_enpassant) {
-            // If we have a take
-            Move::Take(from, to_take) => {
-                if let Piece::Pawn(ref p) = board.board[to_take.0 as usize][to_take.1 as usize] {
-                    if p.headstart {
-                        Self::EnPassant(from, to)
-                    }
-                    else {
-                        Self::Invalid
-                    }
-                }
-                else {
-                    Self::Invalid
-                }
-            },
-            _ => Self::Invalid
+    pub fn from(&self) -> Option<Position> {
+        match self {
+            Self::Move(from, _) | Self::Take(from, _) | Self::EnPassant(from, _) | Self::Promote(from, _, _) => Some(*from),
+            _ => None
         }
     }
 
@@ -449,147 +362,35 @@ impl Move {
             Self::Move(_, to) => Some(to.clone()),
             Self::Take(_, to) => Some(to.clone()),
             Self::EnPassant(_, to) => Some(to.clone()),
+            Self::Promote(_, to, _) | Self::Drop(_, _, to) => Some(*to),
+            // Where the king lands from its standard square on the e-file
             Self::KingsideCastle(c) => match c {
-                Color::Black => Some((0, 1)),
+                Color::Black => Some((0, 6)),
                 Color::White => Some((7, 6))
             },
             Self::QueensideCastle(c) => match c {
-                Color::Black => Some((0, 6)),
-                Color::White => Some((7, 1))                
+                Color::Black => Some((0, 2)),
+                Color::White => Some((7, 2))
             }
-            _ => None
         }
     }
 
-    pub fn is_promotable(&self, board: &ChessBoard) -> bool {
+    pub fn promotion(&self) -> Option<CanPromoteTo> {
         match self {
-            Self::Move(_, to) => {
-                let dst = &board.board[to.0 as usize][to.1 as usize];
-                match (dst.get_type(), dst.color(), to) {
-                    (Some(PieceType::Pawn), Some(Color::Black), (0, _)) => true,
-                    (Some(PieceType::Pawn), Some(Color::White), (7, _)) => true,
-                    _ => false
-                }
-            },
-            _ => false
+            Self::Promote(_, _, p) => Some(p.clone()),
+            _ => None
         }
     }
 
-    pub fn is_valid(&self, board: &ChessBoard) -> bool {
+    // The same pawn move promoting to another piece, any other move is left as is
+    pub fn with_promotion(&self, p: CanPromoteTo) -> Self {
         match self {
-            Self::Invalid => false,
-            Self::Defend(_) => false,
-            Self::Take(_, to) |  Self::Move(_, to) => {
-                board.board[to.0 as usize][to.1 as usize].get_type() != Some(PieceType::King)
-            }
-            _ => true
-        }
-    }
-}
-
-fn diag_line_generic(p: &impl PieceCommon, board: &ChessBoard, n_travel: i8, pos_trans: impl Fn(i8) -> Position) -> Vec<Move> {
-    let mut moves = vec!();
-    for i in 1..n_travel {
-        let to = pos_trans(i);
-        let move_ = Move::new(p, board, to);
-        match move_ {
-            Move::Move(_, _) =>{
-                moves.push(move_);
-            },
-            Move::Take(_, _) => {
-                moves.push(move_);
-                break;
-            },
-            Move::Invalid => {
-                break;
-            },
-            Move::Defend(_) => {
-                moves.push(move_);
-                break;
-            }
-            _ => panic!("This case shoud never happn, check the code")
+            Self::Promote(from, to, _) => Self::Promote(*from, *to, p),
+            other => other.clone()
         }
     }
-    moves
 }
 
-// fn check_attack_vector(p: &impl PieceCommon, direction: VectorDirection, attack_vectors: &HashMap<VectorDirection, AttackVector>) -> bool {
-//     match direction {
-//         VectorDirection::UP | VectorDirection::RIGHT => attack_vectors.get(Vec)
-//     }
-// }
-
-fn line_left(p: &impl PieceCommon, board: &ChessBoard) -> Vec<Move> {
-    let pos = p.position();
-    let n_travel = pos.1 + 1;
-    let pos_trans = |i: i8| {
-        (pos.0, pos.1 - i)
-    };
-    diag_line_generic(p, board, n_travel, pos_trans)
-}
-
-fn line_right(p: &impl PieceCommon, board: &ChessBoard) -> Vec<Move> {
-    let pos = p.position();
-    let n_travel = 8 - pos.1;
-    let pos_trans = |i: i8| {
-        (pos.0, pos.1 + i)
-    };
-    diag_line_generic(p, board, n_travel, pos_trans)
-}
-
-fn line_up(p: &impl PieceCommon, board: &ChessBoard) -> Vec<Move> {
-    let pos = p.position();
-    let n_travel = pos.0 + 1;
-    let pos_trans = |i: i8| {
-        (pos.0 - i, pos.1)
-    };
-    diag_line_generic(p, board, n_travel, pos_trans)
-}
-
-fn line_down(p: &impl PieceCommon, board: &ChessBoard) -> Vec<Move> {
-    let pos = p.position();
-    let n_travel = 8 - pos.0;
-    let pos_trans = |i: i8| {
-        (pos.0 + i, pos.1)
-    };
-    diag_line_generic(p, board, n_travel, pos_trans)
-}
-
-fn diag_left_up(p: &impl PieceCommon, board: &ChessBoard) -> Vec<Move> {
-    let pos = p.position();
-    let n_travel = std::cmp::min(pos.1 + 1, pos.0 + 1);
-    let pos_trans = |i: i8| {
-        (pos.0 - i, pos.1 - i)
-    };
-    diag_line_generic(p, board, n_travel, pos_trans)
-}
-
-fn diag_right_up(p: &impl PieceCommon, board: &ChessBoard) -> Vec<Move> {
-    let pos = p.position();
-    let n_travel = std::cmp::min(8 - pos.1, pos.0 + 1);
-    let pos_trans = |i: i8| {
-        (pos.0 - i, pos.1 + i)
-    };
-    diag_line_generic(p, board, n_travel, pos_trans)
-}
-
-fn diag_left_down(p: &impl PieceCommon, board: &ChessBoard) -> Vec<Move> {
-    let pos = p.position();
-    let n_travel =  std::cmp::min(pos.1 + 1, 8 - pos.0);
-    let pos_trans = |i: i8| {
-        (pos.0 + i, pos.1 - i)
-    };
-    diag_line_generic(p, board, n_travel, pos_trans)
-}
-
-fn diag_right_down(p: &impl PieceCommon, board: &ChessBoard) -> Vec<Move> {
-    let pos = p.position();
-    let n_travel =  std::cmp::min(8 - pos.1, 8 - pos.0);
-    let pos_trans = |i: i8| {
-        (pos.0 + i, pos.1 + i)
-    };
-    diag_line_generic(p, board, n_travel, pos_trans)
-}
 
 /// 
 pub trait PieceCommon {
@@ -599,8 +400,6 @@ pub trait PieceCommon {
 
     fn position(&self) -> Position;
 
-    fn gen_moves(&self, board: &ChessBoard) -> Vec<Move>;
-
     fn emoji_repr(&self) -> &'static str;
 
     fn get_type(&self) -> PieceType;
@@ -613,9 +412,7 @@ pub struct Pawn {
     id: usize,
     color: Color,
     pub position: Position,
-    pub has_moved: bool,
-    pub headstart: bool,
-    pin_vector: Option<VectorDirection>
+    pub has_moved: bool
 }
 
 impl PieceCommon for Pawn {
@@ -624,9 +421,7 @@ impl PieceCommon for Pawn {
             color: color,
             position: p,
             id: id,
-            has_moved: false,
-            headstart: false,
-            pin_vector: None
+            has_moved: false
         }
     }
 
@@ -642,69 +437,6 @@ impl PieceCommon for Pawn {
         self.id
     }
 
-    // Pawn moves are actually the hardest to generate
-    fn gen_moves(&self, board: &ChessBoard) -> Vec<Move> {
-        let mut pin_updown = false;
-        let mut pin_downleft_rightup = false;
-        let mut pin_downright_leftup = false;
-        match self.pin_vector {
-            Some(VectorDirection::LEFT) | Some(VectorDirection::RIGHT) => return vec!(),
-            Some(VectorDirection::UP) | Some(VectorDirection::DOWN) => pin_updown = true,
-            Some(VectorDirection::DOWN_LEFT) | Some(VectorDirection::UP_RIGHT) => pin_downleft_rightup = true,
-            Some(VectorDirection::DOWN_RIGHT) | Some(VectorDirection::UP_LEFT) => pin_downright_leftup = true,
-            _ => {}
-        }
-        let mut moves = vec!();
-        let pos = self.position();
-        let direction = match &self.color {
-            &Color::Black => 1,
-            &Color::White => -1
-        };
-
-        // The basic move
-        let base_move = Move::new(self, board, (pos.0 + direction, pos.1));
-        
-        let base_move_cond = match &base_move {
-            Move::Move(_, _) => true,
-            _ => false
-        };
-        if base_move_cond && !pin_downright_leftup && !pin_downleft_rightup{
-            // If the game starts, we can move two squares
-            moves.push(base_move);
-            if !self.has_moved {
-                let second_move = Move::new(self, board, (pos.0 + direction * 2, pos.1));
-                if let Move::Move(_, _) = second_move {
-                    moves.push(second_move);
-                }
-            }
-        }
-
-        // Taking enemy pieces on the side, en passant is handled here
-        let take_left = Move::new_with_enpassant(self, board, (pos.0 + direction, pos.1 + direction));
-        let take_right = Move::new_with_enpassant(self, board, (pos.0 + direction, pos.1 - direction));
-        if let Move::Take(_, _) = take_left {
-            if !pin_downleft_rightup && !pin_updown{
-                moves.push(take_left);
-            }
-        }
-        else if let Move::EnPassant(_, _) = take_left {
-            if !pin_downleft_rightup && !pin_updown{
-                moves.push(take_left);
-            }
-        }
-        if let Move::EnPassant(_, _) = take_right {
-            if !pin_downright_leftup && !pin_updown{
-                moves.push(take_right);
-            }
-        }
-        else if let Move::Take(_, _) = take_right  {
-            if !pin_downright_leftup && !pin_updown{
-                moves.push(take_right);
-            }
-        }
-        moves
-    }
-
     fn emoji_repr(&self) -> &'static str {
         match self.color {
             Color::Black => "♟",
@@ -721,8 +453,7 @@ impl PieceCommon for Pawn {
 pub struct Knight {
     color: Color,
     id: usize,
-    position: Position,
-    pin_vector: Option<VectorDirection>
+    position: Position
 }
 
 impl PieceCommon for Knight {
@@ -730,8 +461,7 @@ impl PieceCommon for Knight {
         Self {
             color: color,
             id: id,
-            position: p,
-            pin_vector: None
+            position: p
         }
     }
 
@@ -743,24 +473,6 @@ impl PieceCommon for Knight {
         self.position.clone()
     }
 
-    fn gen_moves(&self, board: &ChessBoard) -> Vec<Move> {
-        // A pinned knight cannot move
-        if self.pin_vector.is_some() {
-            return vec!()
-        }
-        // ad - hoc moves
-        vec![
-            Move::new(self, board, (self.position.0 - 2, self.position.1 - 1)),
-            Move::new(self, board, (self.position.0 - 2, self.position.1 + 1)),
-            Move::new(self, board, (self.position.0 + 2, self.position.1 - 1)),
-            Move::new(self, board, (self.position.0 + 2, self.position.1 + 1)),
-            Move::new(self, board, (self.position.0 - 1, self.position.1 - 2)),
-            Move::new(self, board, (self.position.0 - 1, self.position.1 + 2)),
-            Move::new(self, board, (self.position.0 + 1, self.position.1 - 2)),
-            Move::new(self, board, (self.position.0 + 1, self.position.1 + 2)),
-        ]
-    }
-
     fn emoji_repr(&self) -> &'static str {
         match self.color {
             Color::Black => "♞",
@@ -782,8 +494,7 @@ pub struct Rook {
     color: Color,
     position: Position,
     id: usize,
-    has_moved: bool,
-    pin_vector: Option<VectorDirection>
+    has_moved: bool
 }
 
 impl PieceCommon for Rook {
@@ -792,8 +503,7 @@ impl PieceCommon for Rook {
             color: color,
             id: id,
             position: p,
-            has_moved: false,
-            pin_vector: None
+            has_moved: false
         }
     }
 
@@ -805,27 +515,6 @@ impl PieceCommon for Rook {
         self.position.clone()
     }
 
-    fn gen_moves(&self, board: &ChessBoard) -> Vec<Move> {
-        let mut moves = vec!();
-        match self.pin_vector {
-            Some(VectorDirection::LEFT) | Some(VectorDirection::RIGHT) => {
-                moves.extend(line_left(self, board));
-                moves.extend(line_right(self, board));
-            },
-            Some(VectorDirection::DOWN) | Some(VectorDirection::UP) => {
-                moves.extend(line_up(self, board));
-                moves.extend(line_down(self, board));
-            },
-            _ => {
-                moves.extend(line_left(self, board));
-                moves.extend(line_right(self, board));
-                moves.extend(line_up(self, board));
-                moves.extend(line_down(self, board));
-            }
-        }
-        moves
-    }
-    
     fn emoji_repr(&self) -> &'static str {
         match self.color {
             Color::Black => "♜",
@@ -846,8 +535,7 @@ impl PieceCommon for Rook {
 pub struct Queen {
     color: Color,
     id: usize,
-    position: Position,
-    pub pin_vector: Option<VectorDirection>
+    position: Position
 }
 
 impl PieceCommon for Queen {
@@ -855,8 +543,7 @@ impl PieceCommon for Queen {
         Self {
             color: color,
             id: id,
-            position: p,
-            pin_vector: None
+            position: p
         }
     }
     fn color(&self) -> Color {
@@ -867,41 +554,6 @@ impl PieceCommon for Queen {
         self.position.clone()
     }
 
-    fn gen_moves(&self, board: &ChessBoard) -> Vec<Move> {
-        let mut moves = vec!();
-        // lines
-        match self.pin_vector {
-            Some(VectorDirection::DOWN) | Some(VectorDirection::UP) => {
-                moves.extend(line_up(self, board));
-                moves.extend(line_down(self, board));
-            },
-            Some(VectorDirection::LEFT) | Some(VectorDirection::RIGHT) => {
-                moves.extend(line_left(self, board));
-                moves.extend(line_right(self, board));
-            },
-            Some(VectorDirection::UP_LEFT) | Some(VectorDirection::DOWN_RIGHT) => {
-                moves.extend(diag_left_up(self, board));
-                moves.extend(diag_right_down(self, board));
-            },
-            Some(VectorDirection::UP_RIGHT) | Some(VectorDirection::DOWN_LEFT) => {
-                moves.extend(diag_left_down(self, board));
-                moves.extend(diag_right_up(self, board));
-            },
-            _ => {
-                moves.extend(line_left(self, board));
-                moves.extend(line_right(self, board));
-                moves.extend(line_up(self, board));
-                moves.extend(line_down(self, board));
-                // diags
-                moves.extend(diag_left_up(self, board));
-                moves.extend(diag_right_up(self, board));
-                moves.extend(diag_left_down(self, board));
-                moves.extend(diag_right_down(self, board));
-            }
-        }
-        moves
-    }
-
     fn emoji_repr(&self) -> &'static str {
         match self.color {
             Color::Black => "♛",
@@ -922,8 +574,7 @@ impl PieceCommon for Queen {
 pub struct Bishop {
     color: Color,
     id: usize,
-    position: Position,
-    pin_vector: Option<VectorDirection>
+    position: Position
 }
 
 impl PieceCommon for Bishop {
@@ -931,8 +582,7 @@ impl PieceCommon for Bishop {
         Self {
             color: color,
             id: id,
-            position: p,
-            pin_vector: None
+            position: p
         }
     }
     fn color(&self) -> Color {
@@ -943,27 +593,6 @@ impl PieceCommon for Bishop {
         self.position.clone()
     }
 
-    fn gen_moves(&self, board: &ChessBoard) -> Vec<Move> {
-        let mut moves = vec!();
-        match self.pin_vector {
-            Some(VectorDirection::DOWN_LEFT) | Some(VectorDirection::UP_RIGHT) => {
-                moves.extend(diag_right_up(self, board));
-                moves.extend(diag_left_down(self, board));
-            }
-            Some(VectorDirection::DOWN_RIGHT) | Some(VectorDirection::UP_LEFT) => {
-                moves.extend(diag_left_up(self, board));
-                moves.extend(diag_right_down(self, board));;
-            }
-            _ => {
-                moves.extend(diag_left_up(self, board));
-                moves.extend(diag_right_up(self, board));
-                moves.extend(diag_left_down(self, board));
-                moves.extend(diag_right_down(self, board));
-            }
-        }
-        moves
-    }
-
     fn emoji_repr(&self) -> &'static str {
         match self.color {
             Color::Black => "♝",
@@ -981,224 +610,23 @@ impl PieceCommon for Bishop {
 }
 
 
-#[allow(non_camel_case_types)]
-#[derive(Clone, Debug)]
-pub enum VectorDirection {
-    UP,
-    DOWN,
-    LEFT,
-    RIGHT,
-    UP_LEFT,
-    UP_RIGHT,
-    DOWN_LEFT,
-    DOWN_RIGHT,
-}
-
-impl VectorDirection {
-    pub fn dir_vec(&self) -> (i8, i8) {
-        match self {
-            Self::UP => (-1, 0),
-            Self::DOWN => (1, 0),
-            Self::LEFT => (0, -1),
-            Self::RIGHT => (0, 1),
-            Self::UP_LEFT => (-1, -1),
-            Self::UP_RIGHT => (-1, 1),
-            Self::DOWN_LEFT => (1, -1),
-            Self::DOWN_RIGHT => (1, 1),
-        }  
-    }
-
-    fn all_directions() -> Vec<Self> {
-        vec![
-            Self::UP,
-            Self::DOWN,
-            Self::LEFT,
-            Self::RIGHT,
-            Self::UP_LEFT,
-            Self::UP_RIGHT,
-            Self::DOWN_LEFT,
-            Self::DOWN_RIGHT,
-        ]
-    }
-    pub fn is_line(&self) -> bool {
-        match self {
-            VectorDirection::DOWN | VectorDirection::UP | VectorDirection::LEFT | VectorDirection::RIGHT => true,
-            _ => false
-        }
-    }
-
-    pub fn is_diagonal(&self) -> bool {
-        match self {
-            VectorDirection::UP_LEFT | VectorDirection::UP_RIGHT | VectorDirection::DOWN_LEFT | VectorDirection::DOWN_RIGHT => true,
-            _ => false
-        }
-    }
-}
-
-
-#[derive(Clone, Debug)]
-pub struct AttackVector {
-    from: Position,
-    to: Position,
-    vec_direction: VectorDirection,
-    x_bounds: (i8, i8),
-    y_bounds: (i8, i8)
-}
-
-impl AttackVector {
-    pub fn new(from: Position, to: Position, vec_direction: VectorDirection) -> Self {
-        let vec =  (to.0 - to.1, from.0 - from.1);
-        Self { 
-            from: from,
-            to: to,
-            vec_direction: vec_direction,
-            x_bounds: (std::cmp::min(from.0, to.0), std::cmp::max(from.0, to.0)),
-            y_bounds: (std::cmp::min(from.1, to.1), std::cmp::max(from.1, to.1))
-        }
-    }
-
-    pub fn contains_position(&self, p: &Position) -> bool {
-        let condi_in = self.x_bounds.0 <= p.0 && p.0 <= self.x_bounds.1 && self.y_bounds.0 <= p.1 && p.1 <= self.y_bounds.1;
-        match &self.vec_direction {
-            VectorDirection::UP | VectorDirection::DOWN => p.1 == self.from.1 && self.from.0 <= p.0 && condi_in,
-            VectorDirection::LEFT | VectorDirection::RIGHT => p.0 == self.from.0 && self.from.1 <= p.0 && condi_in,
-            VectorDirection::DOWN_LEFT | VectorDirection::UP_RIGHT => self.from.0 + self.from.1 == p.0 + p.1 && condi_in,
-            VectorDirection::DOWN_RIGHT | VectorDirection::UP_LEFT  => self.from.0 - self.from.1 == p.0 - p.1 && condi_in,
-        }
-    }
-}
 
 #[derive(Clone, Debug)]
 pub struct King {
     color: Color,
     id: usize,
     position: Position,
-    has_moved: bool,
-    pub check: bool,
-    pub direct_attacks: Vec<HashSet<Position>>
+    has_moved: bool
 }
 
 impl King {
-    fn generic_attack_vector(self_pos: Position, board: &mut ChessBoard, direction: &VectorDirection) {
-        let mut current_defender_pos: Position = (-1, -1);
-        let mut n_defenders = 0;
-        let dst = &board.board[self_pos.0 as usize][self_pos.1 as usize];
-        let mut pos = dst.position().unwrap();
-        let color = dst.color().unwrap();
-        let dir_vec = direction.dir_vec();
-        let mut curr_vec = HashSet::new();
-        loop {
-            pos = (pos.0 + dir_vec.0, pos.1 + dir_vec.1);
-            if pos.0 < 0 || pos.0 > 7 || pos.1 < 0 || pos.1 > 7 {
-                break;
-            }
-            curr_vec.insert(pos.clone());
-            let dst = &board.board[pos.0 as usize][pos.1 as usize];
-            match dst.color() {
-                Some(c) if c == color => {
-                    n_defenders += 1;
-                    current_defender_pos = dst.position().unwrap();
-                },
-                // A pin
-                Some(c) if c != color && n_defenders == 1 => {
-                    if dst.can_attack(&direction) {
-                        board.board[current_defender_pos.0 as usize][current_defender_pos.1 as usize].set_attack_vector(Some(direction.clone()));
-                    }
-                    break;
-                }
-                // A check
-                Some(c) if c != color && n_defenders == 0 => {
-                    if dst.can_attack(&direction) {
-                        match board.board[self_pos.0 as usize][self_pos.1 as usize] {
-                            Piece::King(ref mut k) => {
-                                k.check = true;
-                                k.direct_attacks.push(curr_vec);
-                            },
-                            _ => panic!("no king")
-                        };
-                    }
-                    break;
-                }
-                _ if n_defenders > 1 => {
-                    break;
-                },
-                _ => {}
-            }
+    pub fn self_by_pos<'a>(pos: &Position, board: &'a mut ChessBoard) -> Result<&'a mut Self, ChessError> {
+        if !(0..8).contains(&pos.0) || !(0..8).contains(&pos.1) {
+            return Err(ChessError::OutOfBoard(*pos))
         }
-    }
-
-    pub fn self_by_pos<'a>(pos: &Position, board: &'a mut ChessBoard) -> &'a mut Self {
         match board.board[pos.0 as usize][pos.1 as usize] {
-            Piece::King(ref mut k) => k,
-            _ => panic!("There should always be two kings")
-        }
-    }
-
-    // if we have a knight attack, we can only take the knight of 
-    pub fn check_for_knight_and_pawn_attack(self_pos: &Position, board: &mut ChessBoard) {
-        // Pawn checking
-        let king_color = King::self_by_pos(self_pos, board).color();
-        let direction = if king_color == Color::White {-1} else {1};
-        let pos1 = (self_pos.0 + direction, self_pos.1 - 1);
-        let pos2 = (self_pos.0 + direction, self_pos.1 + 1);
-        for pos in [pos1, pos2] {
-            if pos.0 < 0 || pos.0 > 7 || pos.1 < 0 || pos.1 > 7 {
-                continue
-            }
-            match board.board[pos.0 as usize][pos.1 as usize] {
-                Piece::Pawn(ref p) if p.color() != king_color => {
-                    let king_mut = King::self_by_pos(self_pos, board);
-                    king_mut.check = true;
-                    king_mut.direct_attacks.push(HashSet::from_iter([pos]));
-                    // Impossible to have both a pawn and a knight attack
-                    return
-                },
-                _ => {}
-            }
-        }
-        // Knight checking
-        let knight_possible_pos = [
-            (self_pos.0 - 2, self_pos.1 - 1),
-            (self_pos.0 - 2, self_pos.1 + 1),
-            (self_pos.0 + 2, self_pos.1 - 1),
-            (self_pos.0 + 2, self_pos.1 + 1),
-            (self_pos.0 - 1, self_pos.1 - 2),
-            (self_pos.0 - 1, self_pos.1 + 2),
-            (self_pos.0 + 1, self_pos.1 - 2),
-            (self_pos.0 + 1, self_pos.1 + 2),
-        ];
-        for pos in knight_possible_pos {
-            if pos.0 < 0 || pos.0 > 7 || pos.1 < 0 || pos.1 > 7 {
-                continue
-            }
-            match board.board[pos.0 as usize][pos.1 as usize] {
-                Piece::Knight(ref kn) if kn.color() != king_color => {
-                    let king_mut = King::self_by_pos(self_pos, board);
-                    king_mut.check = true;
-                    king_mut.direct_attacks.push(HashSet::from_iter([pos]));
-                    break;
-                }
-                _ => {}
-            }
-        }
-    }
-
-    // Add the pin vectors to pieces
-    // We also add directly attacking pieces
-    // If the attacking piece is not a knight: we set a vector otherwise, we set 
-    // If two attackers --> move king into non controlled territory
-    // If 1 attacker && knight --> gen all moves on the vector / take of the offending piece
-    pub fn gen_attack_vectors(self_pos: Position, board: &mut ChessBoard) {
-        match board.board[self_pos.0 as usize][self_pos.1 as usize] {
-            Piece::King(ref mut k) => {
-                k.direct_attacks.clear();
-                k.check = false;
-            },
-            _ => panic!("no king")
-        };
-        Self::check_for_knight_and_pawn_attack(&self_pos, board);
-        for direction in VectorDirection::all_directions().iter() {
-            Self::generic_attack_vector(self_pos, board, direction)
+            Piece::King(ref mut k) => Ok(k),
+            _ => Err(ChessError::NotAKing(*pos))
         }
     }
 }
@@ -1209,9 +637,7 @@ impl PieceCommon for King {
             color: color,
             id: id,
             position: p,
-            has_moved: false,
-            check: false,
-            direct_attacks: vec!()
+            has_moved: false
         }
     }
 
@@ -1223,83 +649,6 @@ impl PieceCommon for King {
         self.position.clone()
     }
 
-    fn gen_moves(&self, board: &ChessBoard) -> Vec<Move> {
-        let filter_moves = |moves: Vec<Move>| -> Vec<Move> {
-            moves
-                .into_iter()
-                .filter(|m| match m {
-                    Move::Move(_,  to) => !board.faction.is_controlled(to, &self.color().other()),
-                    Move::Take(_, to) => !board.faction.is_controlled(to, &self.color().other()),
-                    _ => true
-                })
-                .collect()
-        };
-        // Ad-hoc moves
-        let mut moves = vec![
-            // Lines
-            Move::new(self, board, (self.position.0, self.position.1 + 1)),
-            Move::new(self, board, (self.position.0, self.position.1 - 1)),
-            Move::new(self, board, (self.position.0 + 1, self.position.1)),
-            Move::new(self, board, (self.position.0 - 1, self.position.1)),
-            // Diags
-            Move::new(self, board, (self.position.0 + 1, self.position.1 + 1)),
-            Move::new(self, board, (self.position.0 + 1, self.position.1 - 1)),
-            Move::new(self, board, (self.position.0 - 1, self.position.1 + 1)),
-            Move::new(self, board, (self.position.0 - 1, self.position.1 - 1)),
-        ];
-        moves = filter_moves(moves);
-        if self.has_moved {
-            return moves
-        }
-        let castle_check = |
-            step_size: i8,
-            mult: i8,
-            rook_pos: Position,
-            player: &Color,
-            opponent_control: &HashSet<Position>
-        | {
-            let ok_empty = (1..=step_size).all(|i| {
-                let pos = (self.position.0, self.position.1 + i * mult);
-                //println!("pos: {:?}, is_empty: {}, opponent control: {}", pos, board.board[pos.0 as usize][pos.1 as usize].is_empty(), opponent_control.contains(&pos));
-                board.board[pos.0 as usize][pos.1 as usize].is_empty() && !opponent_control.contains(&pos)
-            });
-            //println!("Condi castle empty: {}, self_moved: {}, rook: {:?}", ok_empty, self.has_moved, player_pieces.get(&rook_pos));
-            let dst = &board.board[rook_pos.0 as usize][rook_pos.1 as usize];
-            match (ok_empty, self.has_moved, dst) {
-                (true, false, Piece::Rook(r)) if !r.has_moved && &r.color() == player => {
-                    true
-                }
-                _ => {
-                    false
-                }
-            }
-        };
-
-        match self.color {
-            Color::White => {
-                // Kingside
-                if castle_check(2, 1, (7, 7), &self.color, &board.faction.black_controlled) {
-                    moves.push(Move::KingsideCastle(self.color.clone()));
-                }
-                // queenside
-                if castle_check(3, -1, (7, 0), &self.color, &board.faction.black_controlled) {
-                    moves.push(Move::QueensideCastle(self.color.clone()));
-                }
-            },
-            Color::Black => {
-                // Kingside
-                if castle_check(2, -1, (0, 0), &self.color, &board.faction.white_controlled) {
-                    moves.push(Move::KingsideCastle(self.color.clone()));
-                }
-                // queenside
-                if castle_check(3, 1, (0, 7), &self.color, &board.faction.white_controlled) {
-                    moves.push(Move::QueensideCastle(self.color.clone()));
-                }
-            }
-        }
-        moves
-    }
-
     fn emoji_repr(&self) -> &'static str {
         match self.color {
             Color::Black => "♚",
@@ -0,0 +1,242 @@
+use std::fmt;
+
+use crate::{
+    error::ChessError,
+    game::GameEngine,
+    piece::{CanPromoteTo, Move, PieceType, Position, parse_square, square_name},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SanError {
+    InvalidSyntax(String),
+    NoMatchingMove(String),
+    Ambiguous(String),
+    MissingPromotion(String),
+}
+
+impl fmt::Display for SanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidSyntax(san) => write!(f, "invalid SAN: {}", san),
+            Self::NoMatchingMove(san) => write!(f, "no legal move matches {}", san),
+            Self::Ambiguous(san) => write!(f, "ambiguous move: {}", san),
+            Self::MissingPromotion(san) => write!(f, "missing or unexpected promotion: {}", san),
+        }
+    }
+}
+
+impl std::error::Error for SanError {}
+
+pub fn piece_letter(ptype: &PieceType) -> Option<char> {
+    match ptype {
+        PieceType::Knight => Some('N'),
+        PieceType::Bishop => Some('B'),
+        PieceType::Rook => Some('R'),
+        PieceType::Queen => Some('Q'),
+        PieceType::King => Some('K'),
+        _ => None
+    }
+}
+
+pub fn promotion_letter(p: &CanPromoteTo) -> char {
+    match p {
+        CanPromoteTo::Knight => 'N',
+        CanPromoteTo::Bishop => 'B',
+        CanPromoteTo::Rook => 'R',
+        CanPromoteTo::Queen => 'Q',
+        CanPromoteTo::King => 'K',
+    }
+}
+
+// Drops are written the same in SAN and UCI: the piece letter, pawns included, then @ and the square
+pub fn drop_notation(ptype: &PieceType, to: &Position) -> String {
+    format!("{}@{}", piece_letter(ptype).unwrap_or('P'), square_name(to))
+}
+
+pub fn parse_drop(s: &str) -> Option<(PieceType, Position)> {
+    let (letter, square) = s.split_once('@')?;
+    let ptype = match letter {
+        "P" | "" => PieceType::Pawn,
+        "N" => PieceType::Knight,
+        "B" => PieceType::Bishop,
+        "R" => PieceType::Rook,
+        "Q" => PieceType::Queen,
+        _ => return None
+    };
+    Some((ptype, parse_square(square)?))
+}
+
+impl Move {
+    // Written against the engine's position, so disambiguation and the check mark follow its variant
+    pub fn to_san(&self, engine: &GameEngine) -> Result<String, ChessError> {
+        let board = &engine.board;
+        let m = self;
+        let mut san = match m {
+            Move::KingsideCastle(_) => "O-O".to_string(),
+            Move::QueensideCastle(_) => "O-O-O".to_string(),
+            Move::Drop(_, ptype, to) => drop_notation(ptype, to),
+            _ => {
+                let (from, to) = m.uci_squares(board).ok_or(ChessError::Unsupported(format!("{:?} in SAN", m)))?;
+                let ptype = board.board[from.0 as usize][from.1 as usize].get_type().ok_or(ChessError::EmptySquare(from))?;
+                let capture = match m {
+                    Move::Take(_, _) | Move::EnPassant(_, _) => true,
+                    Move::Promote(_, to, _) => !board.board[to.0 as usize][to.1 as usize].is_empty(),
+                    _ => false
+                };
+                let mut san = String::new();
+                match piece_letter(&ptype) {
+                    Some(letter) => {
+                        san.push(letter);
+                        // Other pieces of the same type that can reach the same square
+                        let others: Vec<_> = engine.gen_all_moves()
+                            .into_iter()
+                            .filter(|o| o != m && o.to() == Some(to))
+                            .filter_map(|o| o.from())
+                            .filter(|f| board.board[f.0 as usize][f.1 as usize].get_type() == Some(ptype.clone()))
+                            .collect();
+                        if !others.is_empty() {
+                            let square = square_name(&from);
+                            if others.iter().all(|o| o.1 != from.1) {
+                                san.push_str(&square[..1]);
+                            }
+                            else if others.iter().all(|o| o.0 != from.0) {
+                                san.push_str(&square[1..]);
+                            }
+                            else {
+                                san.push_str(&square);
+                            }
+                        }
+                    },
+                    None if capture => san.push_str(&square_name(&from)[..1]),
+                    None => {}
+                }
+                if capture {
+                    san.push('x');
+                }
+                san.push_str(&square_name(&to));
+                if let Some(p) = m.promotion() {
+                    san.push('=');
+                    san.push(promotion_letter(&p));
+                }
+                san
+            }
+        };
+        let mut after = board.clone();
+        after.make_move(m.clone())?;
+        engine.variant.after_move(&mut after, m, board.captured(m).as_ref())?;
+        let defender = after.side_to_move.clone();
+        if !after.attack_vectors(&defender).is_empty() {
+            san.push(if engine.variant.legal_moves(&after, &defender).is_empty() { '#' } else { '+' });
+        }
+        Ok(san)
+    }
+
+    // Resolves a SAN string against the legal moves
+    pub fn from_san(san: &str, engine: &GameEngine) -> Result<Move, SanError> {
+        let trimmed = san.trim().trim_end_matches(['+', '#', '!', '?']);
+        let moves = engine.gen_all_moves();
+        let castle = match trimmed {
+            "O-O" | "0-0" => Some(moves.iter().find(|m| matches!(m, Move::KingsideCastle(_)))),
+            "O-O-O" | "0-0-0" => Some(moves.iter().find(|m| matches!(m, Move::QueensideCastle(_)))),
+            _ => None
+        };
+        if let Some(castle) = castle {
+            return castle.cloned().ok_or_else(|| SanError::NoMatchingMove(san.into()))
+        }
+        if trimmed.contains('@') {
+            let (ptype, to) = parse_drop(trimmed).ok_or_else(|| SanError::InvalidSyntax(san.into()))?;
+            return moves.into_iter()
+                .find(|m| matches!(m, Move::Drop(_, p, t) if *p == ptype && *t == to))
+                .ok_or_else(|| SanError::NoMatchingMove(san.into()))
+        }
+
+        let mut chars: Vec<char> = trimmed.chars().filter(|c| *c != 'x' && *c != ':' && *c != '=').collect();
+        let promotion = match chars.last() {
+            Some('Q') => Some(CanPromoteTo::Queen),
+            Some('R') => Some(CanPromoteTo::Rook),
+            Some('B') => Some(CanPromoteTo::Bishop),
+            Some('N') => Some(CanPromoteTo::Knight),
+            Some('K') => Some(CanPromoteTo::King),
+            _ => None
+        };
+        if promotion.is_some() {
+            chars.pop();
+        }
+        let ptype = match chars.first() {
+            Some('N') => PieceType::Knight,
+            Some('B') => PieceType::Bishop,
+            Some('R') => PieceType::Rook,
+            Some('Q') => PieceType::Queen,
+            Some('K') => PieceType::King,
+            _ => PieceType::Pawn
+        };
+        if ptype != PieceType::Pawn {
+            chars.remove(0);
+        }
+        if chars.len() < 2 || chars.len() > 4 {
+            return Err(SanError::InvalidSyntax(san.into()))
+        }
+        let dest: String = chars[chars.len() - 2..].iter().collect();
+        let dest = parse_square(&dest).ok_or_else(|| SanError::InvalidSyntax(san.into()))?;
+        let mut from_col = None;
+        let mut from_row = None;
+        for c in &chars[..chars.len() - 2] {
+            match c {
+                'a'..='h' if from_col.is_none() => from_col = Some((*c as u8 - b'a') as i8),
+                '1'..='8' if from_row.is_none() => from_row = Some((b'8' - *c as u8) as i8),
+                _ => return Err(SanError::InvalidSyntax(san.into()))
+            }
+        }
+
+        let candidates: Vec<Move> = moves.into_iter().filter(|m| {
+            match (m.from(), m.to()) {
+                (Some(from), Some(to)) => {
+                    to == dest
+                        && engine.board.board[from.0 as usize][from.1 as usize].get_type() == Some(ptype.clone())
+                        && from_col.is_none_or(|c| c == from.1)
+                        && from_row.is_none_or(|r| r == from.0)
+                },
+                _ => false
+            }
+        })
+        .collect();
+        if candidates.is_empty() {
+            return Err(SanError::NoMatchingMove(san.into()))
+        }
+        // Every promotion piece gives its own move, the letter picks one of them
+        let mut candidates = candidates.into_iter().filter(|m| m.promotion() == promotion);
+        let m = candidates.next().ok_or_else(|| SanError::MissingPromotion(san.into()))?;
+        if candidates.next().is_some() {
+            return Err(SanError::Ambiguous(san.into()))
+        }
+        Ok(m)
+    }
+}
+
+
+#[test]
+fn test_san() {
+    use crate::chessbord::ChessBoard;
+    let engine = GameEngine::from_fen("r3k2r/1P6/8/3Pp3/8/2N3N1/8/R3K2R w KQkq e6 0 1").unwrap();
+    let san = |m: Move| m.to_san(&engine).unwrap();
+    assert_eq!(san(Move::Move((5, 2), (4, 4))), "Nce4");
+    assert_eq!(san(Move::EnPassant((3, 3), (2, 4))), "dxe6");
+    assert_eq!(san(Move::Promote((1, 1), (0, 0), CanPromoteTo::Queen)), "bxa8=Q+");
+    assert_eq!(san(Move::QueensideCastle(crate::piece::Color::White)), "O-O-O");
+    assert_eq!(Move::from_san("Nge4", &engine), Ok(Move::Move((5, 6), (4, 4))));
+    assert_eq!(Move::from_san("O-O", &engine), Ok(Move::KingsideCastle(crate::piece::Color::White)));
+    assert_eq!(Move::from_san("Ne4", &engine), Err(SanError::Ambiguous("Ne4".into())));
+    assert_eq!(Move::from_san("b8", &engine), Err(SanError::MissingPromotion("b8".into())));
+    assert_eq!(
+        Move::from_san("b8=N", &engine),
+        Ok(Move::Promote((1, 1), (0, 1), CanPromoteTo::Knight))
+    );
+    assert!(matches!(Move::from_san("Qd1", &engine), Err(SanError::NoMatchingMove(_))));
+
+    // A Crazyhouse back rank check is no mate while a piece can be dropped in between
+    let fen = "7k/6pp/8/8/8/8/8/R5K1[n] w - - 0 1";
+    let standard = GameEngine::from_board(ChessBoard::from_fen(fen).unwrap());
+    let crazyhouse = GameEngine::from_board_and_variant(ChessBoard::from_fen(fen).unwrap(), std::sync::Arc::new(crate::variant::Crazyhouse));
+    assert_eq!(Move::Move((7, 0), (0, 0)).to_san(&standard).unwrap(), "Ra8#");
+    assert_eq!(Move::Move((7, 0), (0, 0)).to_san(&crazyhouse).unwrap(), "Ra8+");
+}
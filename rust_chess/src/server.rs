@@ -1,19 +1,53 @@
 use std::collections::HashMap;
 
 use actix::prelude::*;
+use rand::Rng;
 use actix_web::web;
 use serde::{Serialize, Deserialize};
 
-use crate::{piece::{Color, Position, Piece, Move, PieceType, CanPromoteTo, King}, chessbord::{WebappRepr, ChessBoard, apply_markers}, game::{GameEngine, Game, Play, Promote, PlayerVsIa, GameWebappRepr, AiVsAi}, ai::{DummyRandomIA, Ai, BestPlayDephtOneAi, MiniMaxAi}};
+use crate::{piece::{Color, Position, Piece, Move, PieceType, CanPromoteTo, King}, chessbord::{WebappRepr, ChessBoard, apply_markers}, game::{GameEngine, Game, Play, Promote, PlayerVsIa, GameWebappRepr, AiVsAi, BughouseGame, BughousePlay, BughouseWebappRepr}, ai::{DummyRandomIA, Ai, BestPlayDephtOneAi, MiniMaxAi}, error::ChessError, variant::VariantKind};
 
 struct ChessActor {
-    game: Option<Box<dyn Game>>
+    game: Option<Box<dyn Game>>,
+    // The position set up by hand, a game can then be started from it
+    editor: ChessBoard,
+    // Played on its own two boards, next to the single board game
+    bughouse: Option<BughouseGame>
 }
 
 impl ChessActor {
     pub fn new() -> Self {
         Self {
-            game: None
+            game: None,
+            editor: ChessBoard::new_default(),
+            bughouse: None
+        }
+    }
+
+    fn start_game(&mut self, mode: GameMode, engine: GameEngine) -> Result<GameWebappRepr, ChessError> {
+        match mode {
+            GameMode::Chess960(index, mode) => {
+                let index = index.unwrap_or_else(|| rand::thread_rng().gen_range(0..960));
+                self.start_game(*mode, GameEngine::from_board(ChessBoard::new_chess960(index)?))
+            },
+            GameMode::Variant(kind, mode) => self.start_game(*mode, GameEngine::with_variant(kind.instantiate())),
+            GameMode::PlayerVsPlayer => Err(ChessError::Unsupported("player vs player games".to_string())),
+            GameMode::PlayerVsAi(player_color, ai_implementation) => {
+                let mut ai= ai_implementation.instantiate(&player_color.other());
+                ai.set_depht(4);
+                let game = PlayerVsIa::from_engine(player_color, ai, engine)?;
+                self.game = Some(Box::new(game));
+                Ok(self.game.as_ref().unwrap().webapp_repr())
+            },
+            GameMode::AiVsAi(white_ai_implementation, black_ai_implementation, white_depht, black_depht) => {
+                let mut black_ai = black_ai_implementation.instantiate(&Color::Black);
+                let mut white_ai = white_ai_implementation.instantiate(&Color::White);
+                black_ai.set_depht(black_depht);
+                white_ai.set_depht(white_depht);
+                let game = AiVsAi::from_engine(black_ai, white_ai, engine);
+                self.game = Some(Box::new(game));
+                Ok(self.game.as_ref().unwrap().webapp_repr())
+            },
         }
     }
 }
@@ -24,75 +58,149 @@ impl Actor for ChessActor {
 
 
 impl Handler<Play> for ChessActor {
-    type Result=Result<GameWebappRepr, ()>;
+    type Result=Result<GameWebappRepr, ChessError>;
 
     fn handle(&mut self, msg: Play, ctx: &mut Self::Context) -> Self::Result {
-        self.game.as_mut().ok_or(()).map(|g| {
-            g.play(msg);
-            g.webapp_repr()
-        })
+        let g = self.game.as_mut().ok_or(ChessError::NoGame)?;
+        g.play(msg)?;
+        Ok(g.webapp_repr())
     }
 }
 
 impl Handler<BoardActions> for ChessActor {
-    type Result=Result<GameWebappRepr, ()>;
+    type Result=Result<GameWebappRepr, ChessError>;
 
     fn handle(&mut self, msg: BoardActions, ctx: &mut Self::Context) -> Self::Result {
         match msg {
-            BoardActions::Setup(mode) => {
-                match mode {
-                    GameMode::PlayerVsPlayer => todo!(),
-                    GameMode::PlayerVsAi(player_color, ai_implementation) => {
-                        let mut ai= ai_implementation.instantiate(&player_color.other());
-                        ai.set_depht(4);
-                        let game = PlayerVsIa::new(player_color, ai);
-                        self.game = Some(Box::new(game));
-                        Ok(self.game.as_ref().unwrap().webapp_repr())
-                    },
-                    GameMode::AiVsAi(white_ai_implementation, black_ai_implementation, white_depht, black_depht) => {
-                        let mut black_ai = black_ai_implementation.instantiate(&Color::Black);
-                        let mut white_ai = white_ai_implementation.instantiate(&Color::White);
-                        black_ai.set_depht(black_depht);
-                        white_ai.set_depht(white_depht);
-                        let game = AiVsAi::new(black_ai, white_ai);
-                        self.game = Some(Box::new(game));
-                        Ok(self.game.as_ref().unwrap().webapp_repr())
-                    },
-                }
+            BoardActions::Setup(mode) => self.start_game(mode, GameEngine::new()),
+            BoardActions::SetupFromEditor(mode) => {
+                self.editor.validate().map_err(ChessError::IllegalPosition)?;
+                self.start_game(mode, GameEngine::from_board(self.editor.clone()))
+            },
+        }
+    }
+}
+
+
+impl Handler<EditBoard> for ChessActor {
+    type Result=Result<WebappRepr, ChessError>;
+
+    fn handle(&mut self, msg: EditBoard, _ctx: &mut Self::Context) -> Self::Result {
+        match msg {
+            EditBoard::PutPiece { x, y, piece, color } => self.editor.put_piece((x as i8, y as i8), piece, color)?,
+            EditBoard::RemovePiece { x, y } => {
+                self.editor.remove_piece((x as i8, y as i8))?;
             },
-            _ => {
-                Err(())
-            }
+            EditBoard::Clear => self.editor.clear(),
+            EditBoard::Reset => self.editor = ChessBoard::new_default(),
+            EditBoard::SetSideToMove(color) => self.editor.set_side_to_move(color),
+            EditBoard::SetCastling { color, kingside, allowed } => self.editor.set_castling(&color, kingside, allowed)?,
         }
+        Ok(self.editor.to_webapp())
     }
 }
 
 
 impl Handler<Promote> for ChessActor {
-    type Result=Result<GameWebappRepr, ()>;
+    type Result=Result<GameWebappRepr, ChessError>;
 
     fn handle(&mut self, msg: Promote, ctx: &mut Self::Context) -> Self::Result {
-        self.game.as_mut().ok_or(()).map(|g| {
-            g.promote(msg);
-            g.webapp_repr()
-        })
+        let g = self.game.as_mut().ok_or(ChessError::NoGame)?;
+        g.promote(msg)?;
+        Ok(g.webapp_repr())
     }
 }
 
 
+impl Handler<SetupBughouse> for ChessActor {
+    type Result=Result<BughouseWebappRepr, ChessError>;
+
+    fn handle(&mut self, msg: SetupBughouse, _ctx: &mut Self::Context) -> Self::Result {
+        // Four ais have to answer quickly, they search less deep than a single one
+        let seat = |ai: Option<AiImplementation>, color: Color| ai.map(|ai| {
+            let mut ai = ai.instantiate(&color);
+            ai.set_depht(2);
+            ai
+        });
+        let [[white_0, black_0], [white_1, black_1]] = msg.seats;
+        let ais = [
+            [seat(white_0, Color::White), seat(black_0, Color::Black)],
+            [seat(white_1, Color::White), seat(black_1, Color::Black)],
+        ];
+        let mut game = BughouseGame::new(std::time::Duration::from_secs(msg.seconds), ais);
+        game.ai_play()?;
+        let repr = game.webapp_repr();
+        self.bughouse = Some(game);
+        Ok(repr)
+    }
+}
+
+impl Handler<BughousePlay> for ChessActor {
+    type Result=Result<BughouseWebappRepr, ChessError>;
+
+    fn handle(&mut self, msg: BughousePlay, _ctx: &mut Self::Context) -> Self::Result {
+        let g = self.bughouse.as_mut().ok_or(ChessError::NoGame)?;
+        g.play(msg)?;
+        Ok(g.webapp_repr())
+    }
+}
+
+
+#[derive(Message)]
+#[rtype(result="Result<String, ChessError>")]
+struct ExportPgn;
+
+impl Handler<ExportPgn> for ChessActor {
+    type Result=Result<String, ChessError>;
+
+    fn handle(&mut self, _msg: ExportPgn, _ctx: &mut Self::Context) -> Self::Result {
+        self.game.as_ref().ok_or(ChessError::NoGame)?.to_pgn()
+    }
+}
+
+
+// Bad requests are the client's fault, a board without kings is ours
+impl actix_web::ResponseError for ChessError {
+    fn status_code(&self) -> actix_web::http::StatusCode {
+        use actix_web::http::StatusCode;
+        match self {
+            ChessError::NoGame => StatusCode::NOT_FOUND,
+            ChessError::IllegalMove(_)
+            | ChessError::OutOfBoard(_)
+            | ChessError::EmptySquare(_)
+            | ChessError::OccupiedSquare(_)
+            | ChessError::CastlingUnavailable(_, _)
+            | ChessError::IllegalPosition(_)
+            | ChessError::InvalidChess960Index(_)
+            | ChessError::EmptyPocket(_, _)
+            | ChessError::NoSuchBoard(_)
+            | ChessError::Fen(_)
+            | ChessError::Pgn(_)
+            | ChessError::San(_)
+            | ChessError::Uci(_) => StatusCode::BAD_REQUEST,
+            ChessError::NoKing(_) | ChessError::NotAKing(_) | ChessError::NoAiMove(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ChessError::Unsupported(_) => StatusCode::NOT_IMPLEMENTED,
+        }
+    }
+}
+
+fn mailbox_error(e: MailboxError) -> actix_web::Error {
+    actix_web::error::ErrorInternalServerError(e.to_string())
+}
+
+
 struct AppData {
     chess_actor: Addr<ChessActor>
 }
 
 
 async fn reset_board(data: web::Data<AppData>, action: web::Json<BoardActions>) -> actix_web::Result<impl actix_web::Responder> {
-    let err = actix_web::error::ErrorInternalServerError("could not process play");
-    let new_board = data.chess_actor.send(action.0).await.unwrap().map_err(|e| err)?;
+    let new_board = data.chess_actor.send(action.0).await.map_err(mailbox_error)??;
     Ok(web::Json(new_board))
 }
 
 #[derive(Serialize, Deserialize, Message)]
-#[rtype(result="Result<GameWebappRepr, ()>")]
+#[rtype(result="Result<GameWebappRepr, ChessError>")]
 pub enum AiImplementation {
     DummyAi,
     BestPlayDephtOneAi,
@@ -111,23 +219,47 @@ impl AiImplementation {
 
 
 #[derive(Serialize, Deserialize, Message)]
-#[rtype(result="Result<GameWebappRepr, ()>")]
+#[rtype(result="Result<GameWebappRepr, ChessError>")]
 pub enum GameMode {
     PlayerVsPlayer,
     PlayerVsAi(Color, AiImplementation),
-    AiVsAi(AiImplementation, AiImplementation, usize, usize)
+    AiVsAi(AiImplementation, AiImplementation, usize, usize),
+    // Plays the inner mode from a Fischer Random position, picked at random without an index
+    Chess960(Option<usize>, Box<GameMode>),
+    // Plays the inner mode under the rules of a variant, from its own starting position
+    Variant(VariantKind, Box<GameMode>)
 }
 
 
 #[derive(Serialize, Deserialize, Message)]
-#[rtype(result="Result<GameWebappRepr, ()>")]
+#[rtype(result="Result<GameWebappRepr, ChessError>")]
 enum BoardActions {
     Setup(GameMode),
+    // Starts the game from the position built with `EditBoard`
+    SetupFromEditor(GameMode),
+}
+
+// The ai of each seat, by board then white first (none for a human), and the time of every player
+#[derive(Serialize, Deserialize, Message)]
+#[rtype(result="Result<BughouseWebappRepr, ChessError>")]
+struct SetupBughouse {
+    seats: [[Option<AiImplementation>; 2]; 2],
+    seconds: u64
+}
+
+#[derive(Serialize, Deserialize, Message)]
+#[rtype(result="Result<WebappRepr, ChessError>")]
+enum EditBoard {
+    PutPiece { x: u8, y: u8, piece: PieceType, color: Color },
+    RemovePiece { x: u8, y: u8 },
+    Clear,
+    Reset,
+    SetSideToMove(Color),
+    SetCastling { color: Color, kingside: bool, allowed: bool },
 }
 
 async fn play(data: web::Data<AppData>, payload: web::Json<Play>) -> actix_web::Result<impl actix_web::Responder> {
-    let err = actix_web::error::ErrorInternalServerError("could not process play");
-    let new_board = data.chess_actor.send(payload.0).await.unwrap().map_err(|_| err)?;
+    let new_board = data.chess_actor.send(payload.0).await.map_err(mailbox_error)??;
     Ok(web::Json(new_board))
 }
 
@@ -143,11 +275,30 @@ async fn set_play_mode(data: web::Data<AppData>, mode: String) -> actix_web::Res
 
 
 async fn promote(data: web::Data<AppData>, payload: web::Json<Promote>) -> actix_web::Result<impl actix_web::Responder> {
-    let err = actix_web::error::ErrorInternalServerError("Bod promotion");
-    let new_board = data.chess_actor.send(payload.0).await.unwrap().map_err(|_| err)?;
+    let new_board = data.chess_actor.send(payload.0).await.map_err(mailbox_error)??;
     Ok(web::Json(new_board))
 }
 
+async fn edit_board(data: web::Data<AppData>, payload: web::Json<EditBoard>) -> actix_web::Result<impl actix_web::Responder> {
+    let board = data.chess_actor.send(payload.0).await.map_err(mailbox_error)??;
+    Ok(web::Json(board))
+}
+
+async fn setup_bughouse(data: web::Data<AppData>, payload: web::Json<SetupBughouse>) -> actix_web::Result<impl actix_web::Responder> {
+    let game = data.chess_actor.send(payload.0).await.map_err(mailbox_error)??;
+    Ok(web::Json(game))
+}
+
+async fn play_bughouse(data: web::Data<AppData>, payload: web::Json<BughousePlay>) -> actix_web::Result<impl actix_web::Responder> {
+    let game = data.chess_actor.send(payload.0).await.map_err(mailbox_error)??;
+    Ok(web::Json(game))
+}
+
+async fn export_pgn(data: web::Data<AppData>) -> actix_web::Result<impl actix_web::Responder> {
+    let pgn = data.chess_actor.send(ExportPgn).await.map_err(mailbox_error)??;
+    Ok(actix_web::HttpResponse::Ok().content_type("application/x-chess-pgn").body(pgn))
+}
+
 //#[actix::main]
 pub async fn run_dev_app() -> std::io::Result<()> {
     let chess_actor = ChessActor::new();
@@ -166,6 +317,10 @@ pub async fn run_dev_app() -> std::io::Result<()> {
             .route("/api/reset_board", web::get().to(reset_board))
             .route("/api/set_play_mode", web::post().to(reset_board))
             .route("/api/promote", web::post().to(promote))
+            .route("/api/pgn", web::get().to(export_pgn))
+            .route("/api/edit_board", web::post().to(edit_board))
+            .route("/api/bughouse/setup", web::post().to(setup_bughouse))
+            .route("/api/bughouse/play", web::post().to(play_bughouse))
             .wrap(cors)
     })
     .bind(("127.0.0.1", 8005))?
@@ -178,6 +333,11 @@ fn test_ser() {
     let game_setup = BoardActions::Setup(GameMode::PlayerVsAi(Color::White, AiImplementation::BestPlayDephtOneAi));
     let ev = serde_json::to_string(&game_setup).unwrap();
     println!("ev: {}", ev);
+    let chess960_setup = BoardActions::Setup(GameMode::Chess960(Some(518), Box::new(GameMode::PlayerVsAi(Color::Black, AiImplementation::MiniMaxAi))));
+    let ev = serde_json::to_string(&chess960_setup).unwrap();
+    assert_eq!(ev, r#"{"Setup":{"Chess960":[518,{"PlayerVsAi":["Black","MiniMaxAi"]}]}}"#);
+    let variant_setup: BoardActions = serde_json::from_str(r#"{"Setup":{"Variant":["ThreeCheck",{"PlayerVsAi":["White","DummyAi"]}]}}"#).unwrap();
+    assert!(matches!(variant_setup, BoardActions::Setup(GameMode::Variant(VariantKind::ThreeCheck, _))));
 
     //let promote_str = "{}"
 }
\ No newline at end of file
@@ -0,0 +1,121 @@
+use std::fmt;
+
+use crate::{
+    chessbord::ChessBoard,
+    game::GameEngine,
+    piece::{CanPromoteTo, Move, Position, parse_square, square_name},
+    san::{drop_notation, parse_drop},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UciError {
+    InvalidSyntax(String),
+    NoMatchingMove(String),
+    MissingPromotion(String),
+}
+
+impl fmt::Display for UciError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidSyntax(uci) => write!(f, "invalid UCI move: {}", uci),
+            Self::NoMatchingMove(uci) => write!(f, "no legal move matches {}", uci),
+            Self::MissingPromotion(uci) => write!(f, "missing or unexpected promotion: {}", uci),
+        }
+    }
+}
+
+impl std::error::Error for UciError {}
+
+fn promotion_char(p: &CanPromoteTo) -> char {
+    match p {
+        CanPromoteTo::Knight => 'n',
+        CanPromoteTo::Bishop => 'b',
+        CanPromoteTo::Rook => 'r',
+        CanPromoteTo::Queen => 'q',
+        CanPromoteTo::King => 'k',
+    }
+}
+
+impl Move {
+    // The (from, to) squares of the moving piece, castling being a king move, onto its own rook in Chess960
+    pub fn uci_squares(&self, board: &ChessBoard) -> Option<(Position, Position)> {
+        match self {
+            Self::KingsideCastle(c) | Self::QueensideCastle(c) => {
+                let kingside = matches!(self, Self::KingsideCastle(_));
+                let (king_from, king_to, rook_from, _) = board.castling_squares(c, kingside).ok()?;
+                Some((king_from, if board.chess960 { rook_from } else { king_to }))
+            },
+            Self::Move(from, to) | Self::Take(from, to) | Self::EnPassant(from, to) | Self::Promote(from, to, _) => Some((*from, *to)),
+            _ => None
+        }
+    }
+
+    pub fn to_uci(&self, board: &ChessBoard) -> String {
+        if let Self::Drop(_, ptype, to) = self {
+            return drop_notation(ptype, to)
+        }
+        let (from, to) = match self.uci_squares(board) {
+            Some(squares) => squares,
+            None => return "0000".into()
+        };
+        let mut uci = format!("{}{}", square_name(&from), square_name(&to));
+        if let Some(p) = self.promotion() {
+            uci.push(promotion_char(&p));
+        }
+        uci
+    }
+
+    // Resolves a long algebraic move against the legal moves
+    pub fn from_uci(uci: &str, engine: &GameEngine) -> Result<Move, UciError> {
+        let uci = uci.trim();
+        if uci.contains('@') {
+            let (ptype, to) = parse_drop(uci).ok_or_else(|| UciError::InvalidSyntax(uci.into()))?;
+            return engine.gen_all_moves()
+                .into_iter()
+                .find(|m| matches!(m, Move::Drop(_, p, t) if *p == ptype && *t == to))
+                .ok_or_else(|| UciError::NoMatchingMove(uci.into()))
+        }
+        if !uci.is_ascii() || uci.len() < 4 || uci.len() > 5 {
+            return Err(UciError::InvalidSyntax(uci.into()))
+        }
+        let from = parse_square(&uci[0..2]).ok_or_else(|| UciError::InvalidSyntax(uci.into()))?;
+        let to = parse_square(&uci[2..4]).ok_or_else(|| UciError::InvalidSyntax(uci.into()))?;
+        let promotion = match uci.chars().nth(4) {
+            None => None,
+            Some('q') => Some(CanPromoteTo::Queen),
+            Some('r') => Some(CanPromoteTo::Rook),
+            Some('b') => Some(CanPromoteTo::Bishop),
+            Some('n') => Some(CanPromoteTo::Knight),
+            Some('k') => Some(CanPromoteTo::King),
+            Some(_) => return Err(UciError::InvalidSyntax(uci.into()))
+        };
+        let candidates: Vec<Move> = engine.gen_all_moves()
+            .into_iter()
+            .filter(|m| m.uci_squares(&engine.board) == Some((from, to)))
+            .collect();
+        if candidates.is_empty() {
+            return Err(UciError::NoMatchingMove(uci.into()))
+        }
+        // The squares match, only the promotion piece can be wrong
+        candidates.into_iter()
+            .find(|m| m.promotion() == promotion)
+            .ok_or_else(|| UciError::MissingPromotion(uci.into()))
+    }
+}
+
+
+#[test]
+fn test_uci_round_trip() {
+    let engine = GameEngine::from_fen("r3k2r/1P6/8/3Pp3/8/2N3N1/8/R3K2R w KQkq e6 0 1").unwrap();
+    for m in engine.gen_all_moves() {
+        let uci = m.to_uci(&engine.board);
+        assert_eq!(Move::from_uci(&uci, &engine), Ok(m));
+    }
+    assert_eq!(Move::Promote((1, 1), (0, 0), CanPromoteTo::Knight).to_uci(&engine.board), "b7a8n");
+    assert_eq!(Move::KingsideCastle(crate::piece::Color::White).to_uci(&engine.board), "e1g1");
+    assert_eq!(Move::QueensideCastle(crate::piece::Color::White).to_uci(&engine.board), "e1c1");
+    assert_eq!(Move::EnPassant((3, 3), (2, 4)).to_uci(&engine.board), "d5e6");
+    assert_eq!(Move::from_uci("b7a8", &engine), Err(UciError::MissingPromotion("b7a8".into())));
+    assert_eq!(Move::from_uci("e2e4", &engine), Err(UciError::NoMatchingMove("e2e4".into())));
+    assert_eq!(Move::from_uci("e9e4", &engine), Err(UciError::InvalidSyntax("e9e4".into())));
+}
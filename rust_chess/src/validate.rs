@@ -0,0 +1,154 @@
+use std::fmt;
+
+use crate::{
+    bitboard::{self, Bitboard, bit},
+    chessbord::{ChessBoard, back_rank},
+    piece::{Color, PieceType, Position, square_name},
+};
+
+const BACK_RANKS: Bitboard = 0xff | 0xff << 56;
+
+// What makes a position unreachable, or unplayable by the engine
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SetupError {
+    KingCount(Color, usize),
+    PawnOnBackRank(Position),
+    OpponentInCheck(Color),
+    InvalidCastling(Color, bool),
+    InvalidEnPassant(Position),
+    TooManyPawns(Color),
+    // More pieces than the missing pawns could have promoted to
+    TooManyPieces(Color),
+}
+
+impl fmt::Display for SetupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::KingCount(c, n) => write!(f, "{:?} has {} kings instead of one", c, n),
+            Self::PawnOnBackRank(pos) => write!(f, "pawn on the back rank at {}", square_name(pos)),
+            Self::OpponentInCheck(c) => write!(f, "{:?} is in check but it's not its turn", c),
+            Self::InvalidCastling(c, kingside) => {
+                let side = if *kingside { "kingside" } else { "queenside" };
+                write!(f, "{:?} can't castle {}, its king or rook is not in place", c, side)
+            },
+            Self::InvalidEnPassant(pos) => write!(f, "no pawn could have just skipped {}", square_name(pos)),
+            Self::TooManyPawns(c) => write!(f, "{:?} has more than 8 pawns", c),
+            Self::TooManyPieces(c) => write!(f, "{:?} has more pieces than promotions can explain", c),
+        }
+    }
+}
+
+impl std::error::Error for SetupError {}
+
+impl ChessBoard {
+    // Lists every problem of the position, the engine relies on none of them occurring
+    pub fn validate(&self) -> Result<(), Vec<SetupError>> {
+        let mut errors = vec!();
+        let bitboards = &self.bitboards;
+        for color in [Color::White, Color::Black] {
+            let count = |ptype: PieceType| bitboards.pieces(&color, &ptype).count_ones() as usize;
+            let kings = count(PieceType::King);
+            if kings != 1 {
+                errors.push(SetupError::KingCount(color.clone(), kings));
+            }
+            let pawns = count(PieceType::Pawn);
+            if pawns > 8 {
+                errors.push(SetupError::TooManyPawns(color.clone()));
+            }
+            let promoted = count(PieceType::Queen).saturating_sub(1)
+                + count(PieceType::Rook).saturating_sub(2)
+                + count(PieceType::Bishop).saturating_sub(2)
+                + count(PieceType::Knight).saturating_sub(2);
+            if promoted > 8usize.saturating_sub(pawns) {
+                errors.push(SetupError::TooManyPieces(color.clone()));
+            }
+            for kingside in [true, false] {
+                if self.castling_rights.has(&color, kingside) && self.castling_pieces(&color, kingside).is_none() {
+                    errors.push(SetupError::InvalidCastling(color.clone(), kingside));
+                }
+            }
+        }
+        let pawns = bitboards.pieces(&Color::White, &PieceType::Pawn) | bitboards.pieces(&Color::Black, &PieceType::Pawn);
+        for sq in bitboard::squares(pawns & BACK_RANKS) {
+            errors.push(SetupError::PawnOnBackRank(bitboard::position(sq)));
+        }
+        let waiting = self.side_to_move.other();
+        if let Some(king) = bitboards.king_square(&waiting) {
+            if bitboards.is_attacked(king, &self.side_to_move) {
+                errors.push(SetupError::OpponentInCheck(waiting));
+            }
+        }
+        if let Some(target) = self.en_passant {
+            if !self.is_en_passant_possible(target) {
+                errors.push(SetupError::InvalidEnPassant(target));
+            }
+        }
+        if errors.is_empty() { Ok(()) } else { Err(errors) }
+    }
+
+    // The pawn of the side that just played stands in front of the target, both squares it crossed being empty
+    fn is_en_passant_possible(&self, target: Position) -> bool {
+        let pusher = self.side_to_move.other();
+        let dir = match pusher {
+            Color::White => -1,
+            Color::Black => 1
+        };
+        let from = (back_rank(&pusher) + dir, target.1);
+        let pawn = (target.0 + dir, target.1);
+        if target.0 != from.0 + dir || !(0..8).contains(&target.1) {
+            return false
+        }
+        let occupied = self.bitboards.occupied();
+        occupied & (bit(&from) | bit(&target)) == 0
+            && self.bitboards.pieces(&pusher, &PieceType::Pawn) & bit(&pawn) != 0
+    }
+
+    // The en passant square only sets the position apart when a pawn of the side to move stands next to the pushed one
+    pub(crate) fn can_take_en_passant(&self) -> bool {
+        let target = match self.en_passant {
+            Some(target) if self.is_en_passant_possible(target) => target,
+            _ => return false
+        };
+        let row = match self.side_to_move {
+            Color::White => target.0 + 1,
+            Color::Black => target.0 - 1
+        };
+        let takers = [target.1 - 1, target.1 + 1].into_iter()
+            .filter(|col| (0..8).contains(col))
+            .fold(0, |takers, col| takers | bit(&(row, col)));
+        self.bitboards.pieces(&self.side_to_move, &PieceType::Pawn) & takers != 0
+    }
+}
+
+
+#[test]
+fn test_validate() {
+    use crate::fen::STARTING_FEN;
+    let problems = |fen: &str| ChessBoard::from_fen(fen).unwrap().validate().err().unwrap_or_default();
+    assert_eq!(problems(STARTING_FEN), vec!());
+    assert_eq!(problems("rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq e6 0 2"), vec!());
+    assert_eq!(problems("4k3/8/8/8/8/8/8/8 w - - 0 1"), vec![SetupError::KingCount(Color::White, 0)]);
+    assert_eq!(problems("4k3/8/8/8/8/8/8/2K1K3 w - - 0 1"), vec![SetupError::KingCount(Color::White, 2)]);
+    assert_eq!(problems("P3k3/8/8/8/8/8/8/4K2p w - - 0 1"), vec![SetupError::PawnOnBackRank((0, 0)), SetupError::PawnOnBackRank((7, 7))]);
+    assert_eq!(problems("4k3/8/8/8/8/8/8/4K2R b - - 0 1"), vec!());
+    assert_eq!(problems("4k3/8/8/8/8/8/8/4R2K w - - 0 1"), vec![SetupError::OpponentInCheck(Color::Black)]);
+    assert_eq!(problems("4k3/8/8/8/8/8/PPPPPPPP/QQQQK3 w - - 0 1"), vec![SetupError::TooManyPieces(Color::White)]);
+    assert_eq!(problems("4k3/8/8/8/8/8/8/QQQQKQQQ b - - 0 1"), vec!());
+    // A d6 target needs d7 and d6 empty, the pawn having landed on d5
+    assert_eq!(problems("4k3/3p4/8/3pP3/8/8/8/4K3 w - d6 0 2"), vec![SetupError::InvalidEnPassant((2, 3))]);
+    // Games refuse what the board accepts
+    assert_eq!(
+        crate::game::GameEngine::from_fen("4k3/8/8/8/8/8/8/4R2K w - - 0 1").err(),
+        Some(crate::fen::FenError::IllegalPosition(vec![SetupError::OpponentInCheck(Color::Black)]))
+    );
+
+    // The editor leaves the board in any state, validation tells what is wrong with it
+    let mut board = ChessBoard::new_default();
+    board.remove_piece((7, 4)).unwrap();
+    board.castling_rights = crate::chessbord::CastlingRights::ALL;
+    assert_eq!(board.validate(), Err(vec![
+        SetupError::KingCount(Color::White, 0),
+        SetupError::InvalidCastling(Color::White, true),
+        SetupError::InvalidCastling(Color::White, false),
+    ]));
+}
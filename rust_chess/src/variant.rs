@@ -0,0 +1,720 @@
+use std::fmt;
+use std::sync::Arc;
+
+use serde::{Serialize, Deserialize};
+
+use crate::{
+    bitboard::{Bitboard, Bitboards, bit, position, square, squares},
+    chessbord::{CastlingRights, ChessBoard},
+    error::ChessError,
+    game::{GameEngine, GameOutcome},
+    piece::{CanPromoteTo, Color, Move, Piece, PieceType, Position},
+};
+
+const HILL: Bitboard = 1 << 27 | 1 << 28 | 1 << 35 | 1 << 36;
+const EIGHTH_RANK: Bitboard = 0xff;
+pub const RACING_KINGS_FEN: &str = "8/8/8/8/8/8/krbnNBRK/qrbnNBRQ w - - 0 1";
+
+// The rules of a game, every hook defaulting to orthodox chess
+pub trait Variant: fmt::Debug + Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn starting_board(&self) -> ChessBoard {
+        ChessBoard::new_default()
+    }
+
+    // The moves the player can choose from, on top of (or instead of) the orthodox legal moves
+    fn legal_moves(&self, board: &ChessBoard, player: &Color) -> Vec<Move> {
+        board.gen_all_moves(player)
+    }
+
+    // The squares `after_move` may change besides the ones of the move itself, so it can be taken back
+    fn side_effect_squares(&self, _board: &ChessBoard, _m: &Move) -> Vec<Position> {
+        vec!()
+    }
+
+    // Called once the move is played on the board, with the piece it took if any, an error takes the move back
+    fn after_move(&self, _board: &mut ChessBoard, _m: &Move, _captured: Option<&Piece>) -> Result<(), ChessError> {
+        Ok(())
+    }
+
+    // A result that shows on the board alone, cheap enough for the ais to look for on every node
+    fn board_outcome(&self, _board: &ChessBoard) -> Option<GameOutcome> {
+        None
+    }
+
+    // How the ais should weigh a material balance, orthodox chess taking it as is
+    fn evaluation(&self, _board: &ChessBoard, material: f64) -> f64 {
+        material
+    }
+
+    // The squares shown to `viewer`, None when nothing is hidden
+    fn visible_squares(&self, _board: &ChessBoard, _viewer: &Color) -> Option<Bitboard> {
+        None
+    }
+
+    // The game as the ai playing `viewer` may know it, None when it sees everything
+    fn player_view(&self, _engine: &GameEngine, _viewer: &Color) -> Option<GameEngine> {
+        None
+    }
+
+    // Whether the game is over, `moves` being the legal moves of the player to move
+    fn outcome(&self, engine: &GameEngine, moves: &[Move]) -> Option<GameOutcome> {
+        self.board_outcome(&engine.board).or_else(|| engine.standard_outcome(moves))
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Standard;
+
+impl Variant for Standard {
+    fn name(&self) -> &'static str {
+        "Standard"
+    }
+}
+
+// The side whose king reaches d4, e4, d5 or e5 wins
+#[derive(Debug, Clone, Copy, Default)]
+pub struct KingOfTheHill;
+
+impl Variant for KingOfTheHill {
+    fn name(&self) -> &'static str {
+        "King of the Hill"
+    }
+
+    fn board_outcome(&self, board: &ChessBoard) -> Option<GameOutcome> {
+        // Only the side that just moved can have walked onto the hill
+        let winner = board.side_to_move.other();
+        let king = board.bitboards.pieces(&winner, &PieceType::King);
+        (king & HILL != 0).then_some(GameOutcome::HillReached { winner })
+    }
+}
+
+// The side giving a third check wins
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ThreeCheck;
+
+impl Variant for ThreeCheck {
+    fn name(&self) -> &'static str {
+        "Three-check"
+    }
+
+    fn after_move(&self, board: &mut ChessBoard, _m: &Move, _captured: Option<&Piece>) -> Result<(), ChessError> {
+        let mover = board.side_to_move.other();
+        let gives_check = board.bitboards.king_square(&board.side_to_move)
+            .is_some_and(|king| board.bitboards.is_attacked(king, &mover));
+        if gives_check {
+            board.add_check(&mover);
+        }
+        Ok(())
+    }
+
+    fn board_outcome(&self, board: &ChessBoard) -> Option<GameOutcome> {
+        [Color::White, Color::Black]
+            .into_iter()
+            .zip(board.checks)
+            .find(|(_, checks)| *checks >= 3)
+            .map(|(winner, _)| GameOutcome::ThirdCheck { winner })
+    }
+}
+
+// No check may be given, the first king on the 8th rank wins
+// Black gets a last move to tie when white gets there first
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RacingKings;
+
+impl RacingKings {
+    fn on_eighth_rank(board: &ChessBoard, color: &Color) -> bool {
+        board.bitboards.pieces(color, &PieceType::King) & EIGHTH_RANK != 0
+    }
+}
+
+impl Variant for RacingKings {
+    fn name(&self) -> &'static str {
+        "Racing Kings"
+    }
+
+    // The position of `RACING_KINGS_FEN`: black on the left half of the first two ranks, white mirrors it on the right
+    fn starting_board(&self) -> ChessBoard {
+        let mut board = ChessBoard::new_empty();
+        let ranks = [
+            (6, [PieceType::King, PieceType::Rook, PieceType::Bishop, PieceType::Knight]),
+            (7, [PieceType::Queen, PieceType::Rook, PieceType::Bishop, PieceType::Knight]),
+        ];
+        for (row, pieces) in ranks {
+            for (col, ptype) in pieces.into_iter().enumerate() {
+                let col = col as i8;
+                board.put_piece((row, col), ptype.clone(), Color::Black).expect("the first four files are on the board");
+                board.put_piece((row, 7 - col), ptype, Color::White).expect("the last four files are on the board");
+            }
+        }
+        board
+    }
+
+    fn legal_moves(&self, board: &ChessBoard, player: &Color) -> Vec<Move> {
+        let them = player.other();
+        board.gen_all_moves(player)
+            .into_iter()
+            .filter(|m| {
+                let after = board.bitboards.after_move(m);
+                !after.king_square(&them).is_some_and(|king| after.is_attacked(king, player))
+            })
+            .collect()
+    }
+
+    fn board_outcome(&self, board: &ChessBoard) -> Option<GameOutcome> {
+        match (Self::on_eighth_rank(board, &Color::White), Self::on_eighth_rank(board, &Color::Black)) {
+            (true, true) => Some(GameOutcome::RaceTied),
+            (false, true) => Some(GameOutcome::RaceWon { winner: Color::Black }),
+            (true, false) => {
+                // Black is still in the race if its king can step onto the 8th rank right away
+                let black_king = board.bitboards.pieces(&Color::Black, &PieceType::King);
+                let catching_up = board.side_to_move == Color::Black
+                    && self.legal_moves(board, &Color::Black).iter().any(|m| {
+                        m.from().is_some_and(|from| bit(&from) & black_king != 0)
+                            && m.to().is_some_and(|to| bit(&to) & EIGHTH_RANK != 0)
+                    });
+                (!catching_up).then_some(GameOutcome::RaceWon { winner: Color::White })
+            },
+            (false, false) => None
+        }
+    }
+
+    // Bare kings still have a race to run
+    fn outcome(&self, engine: &GameEngine, moves: &[Move]) -> Option<GameOutcome> {
+        self.board_outcome(&engine.board)
+            .or_else(|| engine.standard_outcome(moves).filter(|o| *o != GameOutcome::InsufficientMaterial))
+    }
+}
+
+// Taken pieces join the pocket of the capturer, and can be dropped back instead of moving
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Crazyhouse;
+
+impl Variant for Crazyhouse {
+    fn name(&self) -> &'static str {
+        "Crazyhouse"
+    }
+
+    fn starting_board(&self) -> ChessBoard {
+        let mut board = ChessBoard::new_default();
+        board.pockets = Some(Default::default());
+        board.refresh_hash();
+        board
+    }
+
+    fn legal_moves(&self, board: &ChessBoard, player: &Color) -> Vec<Move> {
+        let mut moves = board.gen_all_moves(player);
+        moves.extend(board.drop_moves(player));
+        moves
+    }
+
+    // Whatever is left on the board, taken pieces come back
+    fn outcome(&self, engine: &GameEngine, moves: &[Move]) -> Option<GameOutcome> {
+        engine.standard_outcome(moves).filter(|o| *o != GameOutcome::InsufficientMaterial)
+    }
+}
+
+// Each board of a Bughouse game plays by the Crazyhouse rules, `BughouseGame` passing the captures to the other board
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Bughouse;
+
+impl Variant for Bughouse {
+    fn name(&self) -> &'static str {
+        "Bughouse"
+    }
+
+    fn starting_board(&self) -> ChessBoard {
+        Crazyhouse.starting_board()
+    }
+
+    fn legal_moves(&self, board: &ChessBoard, player: &Color) -> Vec<Move> {
+        Crazyhouse.legal_moves(board, player)
+    }
+
+    fn outcome(&self, engine: &GameEngine, moves: &[Move]) -> Option<GameOutcome> {
+        Crazyhouse.outcome(engine, moves)
+    }
+}
+
+// A capture explodes the capturer along with every piece but pawns around the target square
+// Kings can't capture, and touching kings can't check each other
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Atomic;
+
+impl Atomic {
+    // The target square and the pieces but pawns around it, once the capturer landed
+    fn blast(bitboards: &Bitboards, to: &Position) -> Bitboard {
+        let pawns = bitboards.pieces(&Color::White, &PieceType::Pawn) | bitboards.pieces(&Color::Black, &PieceType::Pawn);
+        let around = Bitboards::attacks(square(to), &Color::White, &PieceType::King, 0);
+        bit(to) | (around & !pawns)
+    }
+
+    // The enemy king gives no check since it can't take, and none at all while the kings touch
+    fn in_check(bitboards: &Bitboards, player: &Color) -> bool {
+        let them = player.other();
+        let (Some(king), Some(enemy_king)) = (bitboards.king_square(player), bitboards.king_square(&them)) else {
+            return false
+        };
+        let kings_touch = Bitboards::attacks(king, player, &PieceType::King, 0) & (1 << enemy_king) != 0;
+        !kings_touch && bitboards.attackers(king, &them, bitboards.occupied()) & !(1 << enemy_king) != 0
+    }
+}
+
+impl Variant for Atomic {
+    fn name(&self) -> &'static str {
+        "Atomic"
+    }
+
+    fn legal_moves(&self, board: &ChessBoard, player: &Color) -> Vec<Move> {
+        let king = board.bitboards.pieces(player, &PieceType::King);
+        let mut moves = board.bitboards.pseudo_legal_moves(player, board.en_passant_target(player));
+        moves.retain(|m| {
+            let captures = board.captured(m).is_some();
+            if captures && m.from().is_some_and(|from| bit(&from) & king != 0) {
+                return false
+            }
+            let mut after = board.bitboards.after_move(m);
+            if let Some(to) = m.to().filter(|_| captures) {
+                after.clear(Self::blast(&after, &to));
+            }
+            // Blowing up the enemy king wins on the spot, whatever the own king faces
+            match (after.king_square(player), after.king_square(&player.other())) {
+                (None, _) => false,
+                (Some(_), None) => true,
+                _ => !Self::in_check(&after, player)
+            }
+        });
+        moves.extend(board.castling_moves(player));
+        moves
+    }
+
+    fn side_effect_squares(&self, board: &ChessBoard, m: &Move) -> Vec<Position> {
+        match m.to().filter(|_| board.captured(m).is_some()) {
+            Some(to) => squares(Bitboards::attacks(square(&to), &Color::White, &PieceType::King, 0)).map(position).collect(),
+            None => vec!()
+        }
+    }
+
+    fn after_move(&self, board: &mut ChessBoard, m: &Move, captured: Option<&Piece>) -> Result<(), ChessError> {
+        match m.to().filter(|_| captured.is_some()) {
+            Some(to) => board.clear_squares(squares(Self::blast(&board.bitboards, &to)).map(position)),
+            None => Ok(())
+        }
+    }
+
+    fn board_outcome(&self, board: &ChessBoard) -> Option<GameOutcome> {
+        [Color::White, Color::Black]
+            .into_iter()
+            .find(|c| board.bitboards.king_square(c).is_none())
+            .map(|loser| GameOutcome::KingExploded { winner: loser.other() })
+    }
+
+    fn outcome(&self, engine: &GameEngine, moves: &[Move]) -> Option<GameOutcome> {
+        if let Some(outcome) = self.board_outcome(&engine.board) {
+            return Some(outcome)
+        }
+        match moves.is_empty() {
+            true if Self::in_check(&engine.board.bitboards, &engine.current_player) => {
+                Some(GameOutcome::Checkmate { winner: engine.current_player.other() })
+            },
+            true => Some(GameOutcome::Stalemate),
+            false => engine.standard_outcome(moves)
+        }
+    }
+}
+
+// Captures are compulsory and kings are ordinary pieces, the first side left without pieces or moves wins
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Antichess;
+
+impl Variant for Antichess {
+    fn name(&self) -> &'static str {
+        "Antichess"
+    }
+
+    fn starting_board(&self) -> ChessBoard {
+        let mut board = ChessBoard::new_default();
+        board.castling_rights = CastlingRights::NONE;
+        board.refresh_hash();
+        board
+    }
+
+    // No checks to care about, and any piece can be taken, kings included
+    fn legal_moves(&self, board: &ChessBoard, player: &Color) -> Vec<Move> {
+        let targets = !board.bitboards.occupancy(player);
+        let mut moves = board.bitboards.moves_onto(player, board.en_passant_target(player), targets);
+        let kings: Vec<Move> = moves.iter()
+            .filter(|m| m.promotion() == Some(CanPromoteTo::Queen))
+            .map(|m| m.with_promotion(CanPromoteTo::King))
+            .collect();
+        moves.extend(kings);
+        if moves.iter().any(|m| board.captured(m).is_some()) {
+            moves.retain(|m| board.captured(m).is_some());
+        }
+        moves
+    }
+
+    fn board_outcome(&self, board: &ChessBoard) -> Option<GameOutcome> {
+        [Color::White, Color::Black]
+            .into_iter()
+            .find(|c| board.bitboards.occupancy(c) == 0)
+            .map(|winner| GameOutcome::NoMovesLeft { winner })
+    }
+
+    // Losing material is the goal
+    fn evaluation(&self, _board: &ChessBoard, material: f64) -> f64 {
+        -material
+    }
+
+    fn outcome(&self, engine: &GameEngine, moves: &[Move]) -> Option<GameOutcome> {
+        if moves.is_empty() {
+            return Some(GameOutcome::NoMovesLeft { winner: engine.current_player.clone() })
+        }
+        engine.standard_outcome(moves).filter(|o| *o != GameOutcome::InsufficientMaterial)
+    }
+}
+
+// Players only see the squares their pieces stand on or can move to, and win by taking the enemy king
+#[derive(Debug, Clone, Default)]
+pub struct FogOfWar {
+    // In a player's view the enemy king may only be out of sight, its absence then decides nothing
+    pub unseen_king: Option<Color>
+}
+
+impl Variant for FogOfWar {
+    fn name(&self) -> &'static str {
+        "Fog of war"
+    }
+
+    // Checks are unknown to the players, so kings may walk into them and be taken
+    fn legal_moves(&self, board: &ChessBoard, player: &Color) -> Vec<Move> {
+        let targets = !board.bitboards.occupancy(player);
+        let mut moves = board.bitboards.moves_onto(player, board.en_passant_target(player), targets);
+        moves.extend(board.castling_moves_through_checks(player));
+        moves
+    }
+
+    fn board_outcome(&self, board: &ChessBoard) -> Option<GameOutcome> {
+        [Color::White, Color::Black]
+            .into_iter()
+            .filter(|c| Some(c) != self.unseen_king.as_ref())
+            .find(|c| board.bitboards.king_square(c).is_none())
+            .map(|loser| GameOutcome::KingCaptured { winner: loser.other() })
+    }
+
+    fn visible_squares(&self, board: &ChessBoard, viewer: &Color) -> Option<Bitboard> {
+        Some(board.visible_squares(viewer))
+    }
+
+    // The enemy pieces out of sight are taken off the board, the moves of the viewer don't depend on them
+    fn player_view(&self, engine: &GameEngine, viewer: &Color) -> Option<GameEngine> {
+        let them = viewer.other();
+        let hidden = engine.board.bitboards.occupancy(&them) & !engine.board.visible_squares(viewer);
+        let mut view = engine.clone();
+        view.board.clear_squares(squares(hidden).map(position)).ok()?;
+        view.board_history.clear();
+        view.position_history = vec![view.board.position_hash()];
+        let unseen_king = view.board.bitboards.king_square(&them).is_none().then_some(them);
+        view.variant = Arc::new(FogOfWar { unseen_king });
+        Some(view)
+    }
+}
+
+// The variants a game can be set up with
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VariantKind {
+    Standard,
+    KingOfTheHill,
+    ThreeCheck,
+    RacingKings,
+    Crazyhouse,
+    Atomic,
+    Antichess,
+    FogOfWar,
+}
+
+impl VariantKind {
+    pub const ALL: [Self; 8] = [
+        Self::Standard,
+        Self::KingOfTheHill,
+        Self::ThreeCheck,
+        Self::RacingKings,
+        Self::Crazyhouse,
+        Self::Atomic,
+        Self::Antichess,
+        Self::FogOfWar,
+    ];
+
+    // The kind whose variant goes by that name, as written in the PGN Variant tag
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.instantiate().name().eq_ignore_ascii_case(name))
+    }
+
+    pub fn instantiate(&self) -> Arc<dyn Variant> {
+        match self {
+            Self::Standard => Arc::new(Standard),
+            Self::KingOfTheHill => Arc::new(KingOfTheHill),
+            Self::ThreeCheck => Arc::new(ThreeCheck),
+            Self::RacingKings => Arc::new(RacingKings),
+            Self::Crazyhouse => Arc::new(Crazyhouse),
+            Self::Atomic => Arc::new(Atomic),
+            Self::Antichess => Arc::new(Antichess),
+            Self::FogOfWar => Arc::new(FogOfWar::default()),
+        }
+    }
+}
+
+
+// A game of the variant from the position, its outcome already known
+#[cfg(test)]
+fn engine_for(fen: &str, variant: Arc<dyn Variant>) -> GameEngine {
+    let mut engine = GameEngine::from_board_and_variant(ChessBoard::from_fen(fen).unwrap(), variant);
+    engine.update_outcome();
+    engine
+}
+
+#[test]
+fn test_variant() {
+    use std::sync::Arc;
+    use crate::zobrist::Zobrist;
+
+    // Capturing pieces are lost along with their victim
+    #[derive(Debug)]
+    struct Kamikaze;
+
+    impl Variant for Kamikaze {
+        fn name(&self) -> &'static str {
+            "Kamikaze"
+        }
+
+        fn side_effect_squares(&self, _board: &ChessBoard, m: &Move) -> Vec<Position> {
+            match m {
+                Move::Take(_, to) => vec![*to],
+                _ => vec!()
+            }
+        }
+
+        fn after_move(&self, board: &mut ChessBoard, m: &Move, _captured: Option<&Piece>) -> Result<(), ChessError> {
+            if let Move::Take(_, to) = m {
+                board.remove_piece(*to)?;
+            }
+            Ok(())
+        }
+
+        // Castling is not part of the game
+        fn legal_moves(&self, board: &ChessBoard, player: &Color) -> Vec<Move> {
+            board.gen_all_moves(player)
+                .into_iter()
+                .filter(|m| !matches!(m, Move::KingsideCastle(_) | Move::QueensideCastle(_)))
+                .collect()
+        }
+    }
+
+    let engine = GameEngine::new();
+    assert_eq!(engine.variant.name(), "Standard");
+    assert_eq!(engine.gen_all_moves().len(), 20);
+
+    let board = ChessBoard::from_fen("4k3/8/8/3p4/4Q3/8/8/R3K3 w Q - 0 1").unwrap();
+    let mut engine = GameEngine::from_board_and_variant(board.clone(), Arc::new(Kamikaze));
+    assert!(!engine.gen_all_moves().contains(&Move::QueensideCastle(Color::White)));
+    assert!(GameEngine::from_board(board).gen_all_moves().contains(&Move::QueensideCastle(Color::White)));
+    let before = engine.board.position_hash();
+    let undo = engine.make_move(&Move::Take((4, 4), (3, 3))).unwrap();
+    assert!(engine.board.board[3][3].is_empty());
+    assert_eq!(engine.board.position_hash(), Zobrist::keys().hash(&engine.board));
+    engine.unmake_move(undo);
+    assert_eq!(engine.board.position_hash(), before);
+    assert_eq!(engine.board.to_fen(), "4k3/8/8/3p4/4Q3/8/8/R3K3 w Q - 0 1");
+
+    // Only the kings and a rook remain, the game goes on
+    engine.try_play(Move::Take((4, 4), (3, 3))).unwrap();
+    assert_eq!(engine.board.to_fen(), "4k3/8/8/8/8/8/8/R3K3 b Q - 0 1");
+    assert_eq!(engine.outcome, None);
+}
+
+#[test]
+fn test_lightweight_variants() {
+    use crate::ai::{Ai, MiniMaxAi};
+    use crate::game::MoveOutcome;
+
+    // Stepping onto the hill wins, and the ai goes for it
+    let mut engine = engine_for("k7/8/8/8/8/3K4/8/7r w - - 0 1", Arc::new(KingOfTheHill));
+    let mut ai = MiniMaxAi::new(Color::White);
+    ai.set_depht(2);
+    let m = ai.play(&engine).unwrap();
+    assert!([Some((4, 3)), Some((4, 4))].contains(&m.to()));
+    assert_eq!(engine.try_play(m).unwrap(), MoveOutcome::GameOver(GameOutcome::HillReached { winner: Color::White }));
+
+    // The third check ends the game, unmaking a check takes it off the count
+    let mut engine = engine_for("4k3/8/8/8/8/8/8/R3K3 w - - 0 1", Arc::new(ThreeCheck));
+    engine.board.checks = [2, 0];
+    engine.board.refresh_hash();
+    let undo = engine.make_move(&Move::Move((7, 0), (0, 0))).unwrap();
+    assert_eq!(engine.board.checks, [3, 0]);
+    engine.unmake_move(undo);
+    assert_eq!(engine.board.checks, [2, 0]);
+    assert_eq!(engine.try_play(Move::Move((7, 4), (6, 4))).unwrap(), MoveOutcome::Played { check: false });
+    assert_eq!(engine.board.checks, [2, 0]);
+    engine.try_play(Move::Move((0, 4), (0, 3))).unwrap();
+    assert_eq!(engine.try_play(Move::Move((7, 0), (7, 3))).unwrap(), MoveOutcome::GameOver(GameOutcome::ThirdCheck { winner: Color::White }));
+
+    // Racing Kings: no move may give check
+    let engine = GameEngine::with_variant(Arc::new(RacingKings));
+    assert_eq!(engine.board.to_fen(), RACING_KINGS_FEN);
+    assert_eq!(engine.gen_all_moves().len(), 21);
+    let engine = engine_for("8/8/8/8/8/k7/8/6RK w - - 0 1", Arc::new(RacingKings));
+    assert!(!engine.gen_all_moves().contains(&Move::Move((7, 6), (5, 6))));
+    assert!(engine.gen_all_moves().contains(&Move::Move((7, 6), (6, 6))));
+    // Black may still tie once white gets to the 8th rank
+    let mut engine = engine_for("8/k6K/8/8/8/8/8/8 w - - 0 1", Arc::new(RacingKings));
+    assert_eq!(engine.try_play(Move::Move((1, 7), (0, 7))).unwrap(), MoveOutcome::Played { check: false });
+    assert_eq!(engine.try_play(Move::Move((1, 0), (0, 0))).unwrap(), MoveOutcome::GameOver(GameOutcome::RaceTied));
+    let mut engine = engine_for("8/7K/8/k7/8/8/8/8 w - - 0 1", Arc::new(RacingKings));
+    assert_eq!(engine.try_play(Move::Move((1, 7), (0, 7))).unwrap(), MoveOutcome::GameOver(GameOutcome::RaceWon { winner: Color::White }));
+}
+
+#[test]
+fn test_crazyhouse() {
+    use crate::game::MoveOutcome;
+    use crate::piece::PieceType;
+    use crate::zobrist::Zobrist;
+
+    let play = |engine: &mut GameEngine, uci: &str| {
+        let m = Move::from_uci(uci, engine).unwrap();
+        engine.try_play(m).unwrap()
+    };
+    let mut engine = GameEngine::with_variant(Arc::new(Crazyhouse));
+    assert_eq!(engine.to_fen(), "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR[] w KQkq - 0 1");
+    for uci in ["e2e4", "d7d5", "e4d5", "d8d5"] {
+        play(&mut engine, uci);
+    }
+    assert_eq!(engine.to_fen(), "rnb1kbnr/ppp1pppp/8/3q4/8/8/PPPP1PPP/RNBQKBNR[Pp] w KQkq - 0 3");
+    assert_eq!(play(&mut engine, "P@e6"), MoveOutcome::Played { check: false });
+    assert_eq!(engine.to_fen(), "rnb1kbnr/ppp1pppp/4P3/3q4/8/8/PPPP1PPP/RNBQKBNR[p] b KQkq - 1 3");
+    assert!(Move::from_uci("N@e4", &engine).is_err());
+
+    // Pawns are never dropped on the first or last rank
+    let board = ChessBoard::from_fen("4k3/8/8/8/8/8/8/4K3[P] w - - 0 1").unwrap();
+    assert_eq!(board.drop_moves(&Color::White).len(), 48);
+    // Under check, drops can only block it
+    let board = ChessBoard::from_fen("4k3/8/8/8/8/8/8/r3K3[Nn] w - - 0 1").unwrap();
+    let blocks: Vec<_> = [(7, 1), (7, 2), (7, 3)].into_iter().map(|to| Move::Drop(Color::White, PieceType::Knight, to)).collect();
+    assert_eq!(board.drop_moves(&Color::White), blocks);
+
+    // Dropping is taken back like any other move
+    let mut board = ChessBoard::from_fen("4k3/8/8/8/8/8/8/4K3[Nn] b - - 0 1").unwrap();
+    let before = board.to_fen();
+    let undo = board.make_move(Move::Drop(Color::Black, PieceType::Knight, (5, 5))).unwrap();
+    assert_eq!(board.to_fen(), "4k3/8/8/8/8/5n2/8/4K3[N] w - - 1 2");
+    assert_eq!(board.position_hash(), Zobrist::keys().hash(&board));
+    board.unmake_move(undo);
+    assert_eq!(board.to_fen(), before);
+
+    // A promoted piece goes back to the pocket as a pawn
+    let board = ChessBoard::from_fen("1r2k3/P7/8/8/8/8/8/4K3[] w - - 0 1").unwrap();
+    let mut engine = GameEngine::from_board_and_variant(board, Arc::new(Crazyhouse));
+    play(&mut engine, "a7a8q");
+    assert_eq!(engine.to_fen(), "Q~r2k3/8/8/8/8/8/8/4K3[] b - - 0 1");
+    assert_eq!(ChessBoard::from_fen(&engine.to_fen()).unwrap().to_fen(), engine.to_fen());
+    play(&mut engine, "b8a8");
+    assert_eq!(engine.to_fen(), "r3k3/8/8/8/8/8/8/4K3[p] w - - 0 2");
+}
+
+#[test]
+fn test_atomic() {
+    use crate::game::MoveOutcome;
+
+    // The queen, the knight and the rook blow up, the pawn stays
+    let mut engine = engine_for("4k3/3r1p2/4n3/8/8/8/4Q3/4K3 w - - 0 1", Arc::new(Atomic));
+    let before = engine.to_fen();
+    let undo = engine.make_move(&Move::Take((6, 4), (2, 4))).unwrap();
+    assert_eq!(engine.to_fen(), "4k3/5p2/8/8/8/8/8/4K3 b - - 0 1");
+    engine.unmake_move(undo);
+    assert_eq!(engine.to_fen(), before);
+    // Both rooks go, and their castles with them
+    let mut engine = engine_for("r3k2r/7p/8/8/8/8/8/4K2R w Kkq - 0 1", Arc::new(Atomic));
+    engine.try_play(Move::Take((7, 7), (1, 7))).unwrap();
+    assert_eq!(engine.to_fen(), "r3k3/8/8/8/8/8/8/4K3 b q - 0 1");
+    assert_eq!(engine.board.position_hash(), crate::zobrist::Zobrist::keys().hash(&engine.board));
+
+    // Blowing up the enemy king wins
+    let mut engine = engine_for("3qk3/8/8/8/8/8/8/3QK3 w - - 0 1", Arc::new(Atomic));
+    assert_eq!(engine.try_play(Move::Take((7, 3), (0, 3))).unwrap(), MoveOutcome::GameOver(GameOutcome::KingExploded { winner: Color::White }));
+
+    // Kings can't capture, nor can a capture blow up its own king
+    let engine = engine_for("7k/8/8/8/8/8/3p4/3QK3 w - - 0 1", Arc::new(Atomic));
+    let moves = engine.gen_all_moves();
+    assert!(!moves.contains(&Move::Take((7, 3), (6, 3))));
+    assert!(!moves.iter().any(|m| matches!(m, Move::Take((7, 4), _))));
+    // Touching kings are no threat to each other
+    let engine = engine_for("8/8/8/8/8/3k4/8/4K3 w - - 0 1", Arc::new(Atomic));
+    assert!(engine.gen_all_moves().contains(&Move::Move((7, 4), (6, 4))));
+}
+
+#[test]
+fn test_antichess() {
+    use crate::ai::{Ai, MiniMaxAi};
+    use crate::game::MoveOutcome;
+
+    let mut engine = GameEngine::with_variant(Arc::new(Antichess));
+    assert_eq!(engine.to_fen(), "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w - - 0 1");
+    assert_eq!(engine.gen_all_moves().len(), 20);
+    // Once e4 and d5 are played, taking is the only option
+    engine.try_play(Move::Move((6, 4), (4, 4))).unwrap();
+    engine.try_play(Move::Move((1, 3), (3, 3))).unwrap();
+    assert_eq!(engine.gen_all_moves(), vec![Move::Take((4, 4), (3, 3))]);
+    assert!(engine.try_play(Move::Move((6, 0), (5, 0))).is_err());
+
+    // Kings can be taken and pawns can become kings
+    let engine = engine_for("8/1P6/8/8/8/8/8/3kK3 w - - 0 1", Arc::new(Antichess));
+    let moves = engine.gen_all_moves();
+    assert_eq!(moves, vec![Move::Take((7, 4), (7, 3))]);
+    let engine = engine_for("8/1P6/8/8/8/8/8/k6K w - - 0 1", Arc::new(Antichess));
+    assert!(engine.gen_all_moves().contains(&Move::Promote((1, 1), (0, 1), CanPromoteTo::King)));
+
+    // Giving away the last piece wins, and the ai goes for it
+    let mut engine = engine_for("7k/8/8/8/8/p7/2R5/8 w - - 0 1", Arc::new(Antichess));
+    let mut ai = MiniMaxAi::new(Color::White);
+    ai.set_depht(2);
+    let m = ai.play(&engine).unwrap();
+    assert_eq!(m, Move::Move((6, 2), (6, 1)));
+    engine.try_play(m).unwrap();
+    assert_eq!(engine.gen_all_moves(), vec![Move::Take((5, 0), (6, 1))]);
+    assert_eq!(engine.try_play(Move::Take((5, 0), (6, 1))).unwrap(), MoveOutcome::GameOver(GameOutcome::NoMovesLeft { winner: Color::White }));
+}
+
+#[test]
+fn test_fog_of_war() {
+    use crate::ai::{Ai, MiniMaxAi};
+    use crate::game::MoveOutcome;
+
+    // At the start each side sees its own half of the board and nothing of the enemy
+    let engine = GameEngine::with_variant(Arc::new(FogOfWar::default()));
+    assert_eq!(engine.board.visible_squares(&Color::White), u64::MAX << 32);
+    let repr = engine.to_webapp_for(&engine.board, &Color::White);
+    assert!(repr[0][4].hidden && repr[0][4].piece.name.is_none());
+    assert!(!repr[7][4].hidden && repr[7][4].piece.name.as_deref() == Some("KING"));
+    assert!(repr.iter().flatten().all(|cell| cell.controll.is_none()));
+    assert!(!engine.to_webapp_for(&engine.board, &Color::Black)[0][4].hidden);
+
+    // Kings are taken like any piece, which ends the game
+    let mut engine = engine_for("4k3/8/8/8/8/8/8/4K2r b - - 0 1", Arc::new(FogOfWar::default()));
+    assert!(engine.gen_all_moves().contains(&Move::Take((7, 7), (7, 4))));
+    assert_eq!(engine.try_play(Move::Take((7, 7), (7, 4))).unwrap(), MoveOutcome::GameOver(GameOutcome::KingCaptured { winner: Color::Black }));
+
+    // The king castles through checks it can't know about
+    let engine = engine_for("4k3/8/8/8/8/8/5r2/4K2R w K - 0 1", Arc::new(FogOfWar::default()));
+    assert!(engine.gen_all_moves().contains(&Move::KingsideCastle(Color::White)));
+
+    // The ai only knows the rook next to its king, the enemy king being out of sight, and its moves stay legal
+    let view = engine.ai_view();
+    assert!(view.board.bitboards.king_square(&Color::Black).is_none());
+    assert_eq!(view.board.bitboards.occupancy(&Color::Black), bit(&(6, 5)));
+    assert_eq!(view.variant.board_outcome(&view.board), None);
+    assert_eq!(view.gen_all_moves(), engine.gen_all_moves());
+    let mut ai = MiniMaxAi::new(Color::White);
+    ai.set_depht(2);
+    let m = ai.play(&view).unwrap();
+    assert!(engine.clone().try_play(m).is_ok());
+}
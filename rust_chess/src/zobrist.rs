@@ -1,148 +1,141 @@
-use crate::{chessbord::ChessBoard, piece::{Color, Move}, game::GameEngine};
+use std::sync::OnceLock;
 
-use super::piece::{PieceType};
+use rand::{Rng, SeedableRng, rngs::StdRng};
+
+use crate::{chessbord::ChessBoard, piece::{Color, Piece, Position}};
+
+static KEYS: OnceLock<Zobrist> = OnceLock::new();
 
 pub struct Zobrist {
     pub table: [[u64; 12]; 64],
-    pub black_to_move: u64
+    pub black_to_move: u64,
+    // One key per castling rights combination, and per en passant file
+    pub castling: [u64; 16],
+    pub en_passant: [u64; 8],
+    // Checks given by each side, the counts past three being of no use
+    pub checks: [[u64; 4]; 2],
+    // Per side and droppable type, one key per count (no more than 16 pawns can be taken)
+    pub pockets: [[[u64; 17]; 5]; 2]
 }
 
 impl Zobrist {
-    pub fn new() -> Self {
+    pub fn new(seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
         let mut table = [[0u64; 12]; 64];
-        for i in 0..64 {
-            for j in 0..12 {
-                table[i][j] = rand::random();
+        for keys in table.iter_mut() {
+            for key in keys.iter_mut() {
+                *key = rng.gen();
             }
         }
-        Self { table: table, black_to_move: rand::random() }
+        Self {
+            table: table,
+            black_to_move: rng.gen(),
+            castling: rng.gen(),
+            en_passant: rng.gen(),
+            checks: rng.gen(),
+            pockets: rng.gen()
+        }
     }
 
-    pub fn hash(&self, board: &ChessBoard, player: &Color) -> u64 {
-        let mut zob_hash = match player {
-            Color::Black => 0u64 ^ self.black_to_move,
-            Color::White => 0u64,
-        };
-        for (_, (x, y)) in &board.faction.white_pieces {
-            let flat_idx = *x as usize * 8 + *y as usize;
-            let zob_piece_id = board.board[*x as usize][*y as usize].get_zobrist_id();
-            let zob = self.table[flat_idx][zob_piece_id as usize];
-            zob_hash = zob_hash ^ zob;
-        }
-        for (_, (x, y)) in &board.faction.black_pieces {
-            let flat_idx = *x as usize * 8 + *y as usize;
-            let zob_piece_id = board.board[*x as usize][*y as usize].get_zobrist_id();
-            let zob = self.table[flat_idx][zob_piece_id as usize];
-            zob_hash = zob_hash ^ zob;
-        }
-        zob_hash
+    // Every board shares the same keys, so their hashes can be compared
+    pub fn keys() -> &'static Self {
+        KEYS.get_or_init(|| Self::new(0x5eed))
     }
 
-    pub fn zob_id_from_pos_and_id(&self, pos: &(i8, i8), piece_zob_id: u64) -> u64 {
-        let flat_idx = pos.0 as usize * 8 + pos.1 as usize;
-        self.table[flat_idx][piece_zob_id as usize]
+    // Empty squares don't contribute to the hash
+    pub fn piece(&self, pos: &Position, piece: &Piece) -> u64 {
+        match piece.get_zobrist_id(pos) {
+            Ok(id) => self.table[pos.0 as usize * 8 + pos.1 as usize][id as usize],
+            Err(_) => 0
+        }
     }
 
-    pub fn update_hash(&self, prev_hash: u64, board: &ChessBoard, play: &Move, curr_player: &Color) -> u64 {
-        let mut next_hash = prev_hash;
-        // We apply a move so we xor in/out the color marker
-        next_hash = next_hash ^ self.black_to_move;
-        
-        match play {
-            Move::EnPassant(from, to) => {
-                let (from_c, to_c) = if from.0 > to.0 {(0, 6)} else {(6, 0)};
-                let zob_idx_from = PieceType::Pawn.get_zobrist_id() + from_c;
-                let zob_idx_to =  PieceType::Pawn.get_zobrist_id() + to_c;
-                let taken_pawn_pos = (to.1, from.0);
-                let zob_from = self.zob_id_from_pos_and_id(from, zob_idx_from);
-                let zob_to = self.zob_id_from_pos_and_id(&taken_pawn_pos, zob_idx_to);
-                next_hash = next_hash ^ zob_from ^ zob_to;
-            },
-            Move::KingsideCastle(c) => {
-                let (add, king_pos_from, king_pos_to, rook_pos_from, rook_pos_to) = match c {
-                    Color::Black => (6, (0, 4), (0, 6), (0, 7), (0, 5)),
-                    Color::White => (0, (7, 4), (7, 6), (7, 7), (7, 5)),
-                };
-                let rook_zob = PieceType::Rook.get_zobrist_id() + add;
-                let king_zob= PieceType::King.get_zobrist_id() + add;
-                let king_from_zid = self.zob_id_from_pos_and_id(&king_pos_from, king_zob);
-                let king_to_zid = self.zob_id_from_pos_and_id(&king_pos_to, king_zob);
-                let rook_from_zid = self.zob_id_from_pos_and_id(&rook_pos_from, rook_zob);
-                let rook_to_zid = self.zob_id_from_pos_and_id(&rook_pos_to, rook_zob);
-                // Applying the zids
-                next_hash = next_hash ^ king_from_zid ^ rook_from_zid ^ king_to_zid ^ rook_to_zid;
-            },
-            Move::Move(from, to) => {
-                let piece_ref_from = &board.board[from.0 as usize][from.1 as usize];
-                let piece_ref_to = &board.board[to.0 as usize][to.1 as usize];
-                let zob_id_from = piece_ref_from.get_zobrist_id();
-                // Xoring out the from
-                next_hash = next_hash ^ self.zob_id_from_pos_and_id(from, zob_id_from);
-                // Xoring out the to if not empty
-                if !piece_ref_to.is_empty() {
-                    let zob_id_to = piece_ref_to.get_zobrist_id();
-                    next_hash = next_hash ^ self.zob_id_from_pos_and_id(to, zob_id_to);
+    // Everything but the pieces: side to move, castling rights, en passant square, checks given and pockets
+    pub fn state(&self, board: &ChessBoard) -> u64 {
+        let mut hash = self.castling[board.castling_rights.0 as usize];
+        for (keys, checks) in self.checks.iter().zip(board.checks) {
+            hash ^= keys[checks.min(3) as usize];
+        }
+        if let Some(pockets) = &board.pockets {
+            for (keys, pocket) in self.pockets.iter().zip(pockets) {
+                for (keys, count) in keys.iter().zip(pocket.0) {
+                    hash ^= keys[count.min(16) as usize];
                 }
-                // Xoring in the from
-                next_hash = next_hash ^ self.zob_id_from_pos_and_id(to, zob_id_from);
-            },
-            Move::Promote(from, to_type) => {
-                let mut add = 0;
-                let piece_ref = &board.board[from.0 as usize][from.1 as usize];
-                if piece_ref.color().unwrap() == Color::Black {
-                    add = 6;
-                };
-                let piece_init_zob = self.zob_id_from_pos_and_id(from, piece_ref.get_zobrist_id());
-                let piece_new_zob = self.zob_id_from_pos_and_id(from, to_type.get_zobrist_id() + add);
-                next_hash = next_hash ^ piece_init_zob ^ piece_new_zob;
-            },
-            Move::QueensideCastle(c) => {
-                let (add, king_pos_from, king_pos_to, rook_pos_from, rook_pos_to) = match c {
-                    Color::Black => (6, (0, 4), (0, 2), (0, 0), (0, 3)),
-                    Color::White => (0, (7, 4), (7, 2), (7, 0), (7, 3)),
-                };
-                let rook_zob = PieceType::Rook.get_zobrist_id() + add;
-                let king_zob= PieceType::King.get_zobrist_id() + add;
-                let king_from_zid = self.zob_id_from_pos_and_id(&king_pos_from, king_zob);
-                let king_to_zid = self.zob_id_from_pos_and_id(&king_pos_to, king_zob);
-                let rook_from_zid = self.zob_id_from_pos_and_id(&rook_pos_from, rook_zob);
-                let rook_to_zid = self.zob_id_from_pos_and_id(&rook_pos_to, rook_zob);
-                // Applying the zids
-                next_hash = next_hash ^ king_from_zid ^ rook_from_zid ^ king_to_zid ^ rook_to_zid;
-            },
-            Move::Take(from, to) => {
-                let piece_ref_from = &board.board[from.0 as usize][from.1 as usize];
-                let piece_ref_to = &board.board[to.0 as usize][to.1 as usize];
-                let zob_id_from = piece_ref_from.get_zobrist_id();
-                let zob_id_to = piece_ref_to.get_zobrist_id();
-                // Xoring out the from
-                next_hash = next_hash ^ self.zob_id_from_pos_and_id(from, zob_id_from);
-                // Xoring out the to (never empty so no ckeck needed)
-                next_hash = next_hash ^ self.zob_id_from_pos_and_id(to, zob_id_to);
-                // Xoring in the from
-                next_hash = next_hash ^ self.zob_id_from_pos_and_id(from, zob_id_from);
-            },
+            }
+        }
+        if board.side_to_move == Color::Black {
+            hash ^= self.black_to_move;
+        }
+        // Like the en passant square of a FEN, only when the capture can be made, so a double push still transposes
+        match board.en_passant {
+            Some((_, col)) if board.can_take_en_passant() => hash ^= self.en_passant[col as usize],
             _ => {}
         }
-        next_hash
+        hash
+    }
+
+    // Full computation, `ChessBoard` keeps its hash up to date incrementally
+    pub fn hash(&self, board: &ChessBoard) -> u64 {
+        let mut hash = self.state(board);
+        for i in 0..8 {
+            for j in 0..8 {
+                hash ^= self.piece(&(i as i8, j as i8), &board.board[i][j]);
+            }
+        }
+        hash
     }
 }
 
 
 #[test]
 fn test_zobrist() {
-    let zob = Zobrist::new();
+    use crate::{game::GameEngine, piece::{Move, CanPromoteTo}};
+    let zob = Zobrist::keys();
     let mut engine = GameEngine::new();
-    engine.board.pprint();
-    let zob_init = zob.hash(&engine.board, &engine.current_player);
-    println!("Zobrist (init): {}", zob_init);
-    let mo = Move::Move((0, 0), (3, 0));
-    let zob_opti = zob.update_hash(zob_init, &engine.board, &mo, &engine.current_player);
-    engine.play_once(mo.clone());
-    engine.finish_turn();
-    engine.prepare_new_turn();
-    engine.board.pprint();
-    println!("curr player: {:?}", engine.current_player);
-    println!("Zobrist (base): {}", zob.hash(&engine.board, &engine.current_player));
-    println!("Zobrist (optimized): {}", zob_opti);
-}
\ No newline at end of file
+    let init = engine.board.position_hash();
+    assert_eq!(init, zob.hash(&engine.board));
+    // Knights going back and forth transpose to the initial position
+    for m in [Move::Move((7, 6), (5, 5)), Move::Move((0, 6), (2, 5)), Move::Move((5, 5), (7, 6)), Move::Move((2, 5), (0, 6))] {
+        assert!(engine.turn == 0 || engine.board.position_hash() != init);
+        engine.play_bypass(m).unwrap();
+        engine.finish_turn();
+        engine.prepare_new_turn();
+    }
+    assert_eq!(engine.board.position_hash(), init);
+    // Only the side to move, the castling rights or the en passant square differ
+    let hash_of = |fen: &str| GameEngine::from_fen(fen).unwrap().board.position_hash();
+    let fen = "r3k2r/8/8/3pP3/8/8/8/R3K2R w KQkq d6 0 1";
+    assert_ne!(hash_of("r3k2r/8/8/3pP3/8/8/8/R3K2R w KQkq - 0 1"), hash_of("r3k2r/8/8/3pP3/8/8/8/R3K2R b KQkq - 0 1"));
+    assert_ne!(hash_of(fen), hash_of("r3k2r/8/8/3pP3/8/8/8/R3K2R w Kkq d6 0 1"));
+    assert_ne!(hash_of(fen), hash_of("r3k2r/8/8/3pP3/8/8/8/R3K2R w KQkq - 0 1"));
+    assert_eq!(hash_of(fen), hash_of("r3k2r/8/8/3pP3/8/8/8/R3K2R w KQkq d6 12 30"));
+    // Every kind of move keeps the incremental hash equal to the full one, and unmaking restores it
+    let moves = [
+        Move::EnPassant((3, 4), (2, 3)),
+        Move::KingsideCastle(Color::White),
+        Move::QueensideCastle(Color::Black),
+        Move::Take((7, 0), (0, 0)),
+    ];
+    for m in moves {
+        let mut board = ChessBoard::from_fen(fen).unwrap();
+        let before = board.position_hash();
+        let undo = board.make_move(m).unwrap();
+        assert_eq!(board.position_hash(), zob.hash(&board));
+        board.unmake_move(undo);
+        assert_eq!(board.position_hash(), before);
+    }
+    // Without a black pawn next to it, e4 leaves the same position as the knights coming back to it later
+    assert_eq!(hash_of("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1"), hash_of("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 4 3"));
+    let mut engine = GameEngine::new();
+    let knights = [Move::Move((0, 6), (2, 5)), Move::Move((7, 6), (5, 5)), Move::Move((2, 5), (0, 6)), Move::Move((5, 5), (7, 6))];
+    for m in std::iter::once(Move::Move((6, 4), (4, 4))).chain(knights.clone()).chain(knights) {
+        assert!(!engine.is_threefold_repetition());
+        engine.play_bypass(m).unwrap();
+        engine.finish_turn();
+        engine.prepare_new_turn();
+    }
+    assert!(engine.is_threefold_repetition());
+    let mut board = ChessBoard::from_fen("1n6/P7/8/8/8/8/8/K6k w - - 0 1").unwrap();
+    board.make_move(Move::Promote((1, 0), (0, 1), CanPromoteTo::Queen)).unwrap();
+    assert_eq!(board.position_hash(), zob.hash(&board));
+}